@@ -0,0 +1,56 @@
+/// Characters of the standard base64 alphabet (RFC 4648)
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes with the standard base64 alphabet, padded with '='
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+
+    for group in data.chunks(3) {
+        let bytes = [group[0], *group.get(1).unwrap_or(&0), *group.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decode text encoded with the standard base64 alphabet. Padding is
+/// optional, whitespace is ignored. Returns None if the text isn't base64
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+
+    for c in text.trim_end_matches(|c: char| c == '=' || c.is_ascii_whitespace()).bytes() {
+        if c.is_ascii_whitespace() {
+            continue;
+        }
+
+        let value = ALPHABET.iter().position(|a| *a == c)?;
+        bits = bits << 6 | value as u32;
+        count += 1;
+
+        if count == 4 {
+            decoded.extend_from_slice(&[(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]);
+            bits = 0;
+            count = 0;
+        }
+    }
+
+    // The last group can have 2 or 3 characters for 1 or 2 bytes
+    match count {
+        0 => {},
+        2 => decoded.push((bits >> 4) as u8),
+        3 => decoded.extend_from_slice(&[(bits >> 10) as u8, (bits >> 2) as u8]),
+        _ => return None
+    }
+
+    Some(decoded)
+}
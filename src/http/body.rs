@@ -1,61 +1,665 @@
-use crate::http::HeaderSet;
+use crate::http::{HeaderSet, Limit, Limits};
 use crate::http::Error;
+use std::fmt;
+use std::io;
 use std::io::{Read, Write};
-use std::io::BufReader;
 use std::io::BufRead;
 
-/// Read the HTTP body from a TCP stream. Can be used for a request or response
-pub fn read_body<T: Read>(headers: &HeaderSet, reader: &mut BufReader<&mut T>) -> Result<Vec<u8>, Error> {
-    if let Some(header) = headers.get("Content-Length") {
-        return read_length(header, reader);
-    } else if let Some(header) = headers.get("Transfer-Encoding") {
-        return read_transfer(header, reader);
+/// Largest chunk accepted in a body using the chunked method
+pub const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Memory reserved up front for a body with a Content-Length. The header
+/// can't be trusted, so larger bodies grow as they are received
+const MAX_RESERVED: u64 = 64 * 1024;
+
+/// Longest line accepted for the size of a chunk, including its extensions
+const MAX_CHUNK_LINE: u64 = 4096;
+
+/// Body of a request or response
+pub enum Body {
+    /// Body held in memory
+    Buffered(Vec<u8>),
+    /// Body read while it's being sent or used, e.g. a large file, or an
+    /// upload read straight from the connection
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+
+    pub fn empty() -> Body {
+        Body::Buffered(Vec::new())
+    }
+
+    /// Create a body read from a stream while it's being sent
+    #[cfg(test)]
+    pub fn stream<R: Read + Send + 'static>(reader: R) -> Body {
+        Body::Stream(Box::new(reader))
+    }
+
+    pub fn is_stream(&self) -> bool {
+        match self {
+            Body::Stream(_) => true,
+            Body::Buffered(_) => false
+        }
+    }
+
+    /// Read a streamed body to its end and keep it in memory
+    pub fn buffer(&mut self) -> Result<&[u8], Error> {
+        if let Body::Stream(reader) = self {
+            let mut data: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut data)?;
+            *self = Body::Buffered(data);
+        }
+
+        match self {
+            Body::Buffered(data) => Ok(data),
+            Body::Stream(_) => Ok(&[])
+        }
+    }
+
+    /// Convert the body to a reader, e.g. to copy it elsewhere
+    pub fn into_reader(self) -> Box<dyn Read + Send> {
+        match self {
+            Body::Buffered(data) => Box::new(io::Cursor::new(data)),
+            Body::Stream(reader) => reader
+        }
+    }
+
+    /// Take the body, leaving an empty one in its place
+    pub fn take(&mut self) -> Body {
+        std::mem::replace(self, Body::empty())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Body {
+        Body::Buffered(data)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(data: &[u8]) -> Body {
+        Body::Buffered(data.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Buffered(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Buffered(text.as_bytes().to_vec())
+    }
+}
+
+/// Shows a buffered body as text, e.g. for logs. A stream isn't read
+impl fmt::Display for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Buffered(data) => write!(f, "{}", String::from_utf8_lossy(data)),
+            Body::Stream(_) => write!(f, "<stream>")
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Buffered(data) => f.debug_tuple("Buffered").field(data).finish(),
+            Body::Stream(_) => f.debug_tuple("Stream").finish()
+        }
+    }
+}
+
+/// How the end of a body read from a connection is found
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Framing {
+    /// There is no body
+    Empty,
+    /// The length is given by the 'Content-Length' header
+    Length(u64),
+    /// The body is sent with the Transfer-Encoding: chunked method
+    Chunked,
+    /// The body ends when the peer closes the connection
+    Close,
+}
+
+impl Framing {
+
+    /// Find how a body is framed from the headers of a request or response.
+    /// Fails if they are invalid or announce a body larger than the limit
+    pub fn from_headers(headers: &HeaderSet, limits: &Limits) -> Result<Framing, Error> {
+        // Transfer-Encoding has priority over Content-Length when both are present
+        if let Some(transfer) = headers.get("Transfer-Encoding") {
+            if !transfer.trim().eq_ignore_ascii_case("chunked") {
+                return Err(Error::header("Unsupported Transfer-Encoding"));
+            }
+            return Ok(Framing::Chunked);
+        }
+
+        if headers.get("Content-Length").is_some() {
+            let length = headers.content_length().ok_or(Error::header("Invalid Content-Length"))?;
+            if length > limits.max_body_size {
+                return Err(Error::TooLarge(Limit::Body));
+            }
+            return Ok(Framing::Length(length));
+        }
+
+        Ok(Framing::Empty)
+    }
+
+    /// Check if the headers give both a Transfer-Encoding and a Content-Length.
+    /// Such a message may be an attempt at smuggling a request past a proxy,
+    /// so its connection must be closed, following RFC 9112 section 6.1
+    pub fn is_ambiguous(headers: &HeaderSet) -> bool {
+        headers.get("Transfer-Encoding").is_some() && headers.get("Content-Length").is_some()
+    }
+}
+
+/// Take a body from a connection without reading it, so that it's read while
+/// it's used. Once the body has been read, the connection is given to `done`
+/// along with whether the body was read to its end, in which case the
+/// connection can be used again. That's right away when there is no body, and
+/// never when the body ends with the connection
+pub fn stream_body<R, F>(reader: R, framing: Framing, limits: &Limits, done: F) -> Body
+    where R: BufRead + Send + 'static,
+          F: FnOnce(R, bool) + Send + 'static
+{
+    let source = match framing {
+        Framing::Empty | Framing::Length(0) => {
+            done(reader, true);
+            return Body::empty();
+        },
+        Framing::Length(length) => Source::Length(reader.take(length)),
+        Framing::Chunked => Source::Chunked(ChunkedReader::new(reader, limits)),
+        Framing::Close => Source::Close(reader.take(limits.max_body_size + 1)),
+    };
+
+    Body::Stream(Box::new(BodyReader {
+        source: Some(source),
+        done: Some(Box::new(done)),
+    }))
+}
+
+/// Where a body read from a connection comes from
+enum Source<R> {
+    Length(io::Take<R>),
+    Chunked(ChunkedReader<R>),
+    /// Reads one byte more than the limit, to tell when it's exceeded
+    Close(io::Take<R>),
+}
+
+/// Body read from a connection while it's used. A body dropped before its end
+/// is skipped so that the connection can still be used
+struct BodyReader<R: BufRead> {
+    source: Option<Source<R>>,
+    done: Option<Box<dyn FnOnce(R, bool) + Send>>,
+}
+
+impl<R: BufRead> BodyReader<R> {
+
+    /// Give the connection back once the body has been read, or once reading
+    /// it failed
+    fn finish(&mut self, complete: bool) {
+        let reader = match self.source.take() {
+            Some(Source::Length(r)) => r.into_inner(),
+            Some(Source::Chunked(r)) => r.into_inner(),
+            _ => return
+        };
+
+        if let Some(done) = self.done.take() {
+            done(reader, complete);
+        }
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = match self.source.as_mut() {
+            Some(Source::Length(r)) => r.read(buf).and_then(|size| {
+                if size == 0 && r.limit() > 0 && !buf.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected end of body"));
+                }
+                Ok(size)
+            }),
+            Some(Source::Chunked(r)) => r.read(buf),
+            Some(Source::Close(r)) => r.read(buf).and_then(|size| {
+                if r.limit() == 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}", Error::TooLarge(Limit::Body))));
+                }
+                Ok(size)
+            }),
+            None => return Ok(0)
+        };
+
+        match result {
+            Ok(0) if !buf.is_empty() => self.finish(true),
+            Err(ref e) if e.kind() != io::ErrorKind::Interrupted => self.finish(false),
+            _ => {}
+        }
+
+        result
+    }
+}
+
+impl<R: BufRead> Drop for BodyReader<R> {
+    fn drop(&mut self) {
+        match self.source {
+            Some(Source::Length(_)) | Some(Source::Chunked(_)) => {
+                let _ = io::copy(self, &mut io::sink());
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Read the HTTP body from a TCP stream. Can be used for a request or response.
+/// Returns the body and the trailers sent after a chunked body. Fails if the
+/// body is larger than the limit
+pub fn read_body<R: BufRead>(headers: &HeaderSet, reader: &mut R, limits: &Limits) -> Result<(Vec<u8>, HeaderSet), Error> {
+    // Transfer-Encoding has priority over Content-Length when both are present
+    if let Some(header) = headers.get("Transfer-Encoding") {
+        return read_transfer(header, reader, limits);
+    } else if headers.get("Content-Length").is_some() {
+        let length = headers.content_length().ok_or(Error::header("Invalid Content-Length"))?;
+        return Ok((read_length(length, reader, limits)?, HeaderSet::new()));
     }
 
-    Ok(Vec::new())
+    Ok((Vec::new(), HeaderSet::new()))
 }
 
 /// Read a body using the Content-Length header
-pub fn read_length<T: Read>(length: String, reader: &mut BufReader<&mut T>) -> Result<Vec<u8>, Error> {
-    let length = length
-        .parse::<usize>()
-        .map_err(|_| Error::header("Invalid Content-Length"))?;
+pub fn read_length<R: BufRead>(length: u64, reader: &mut R, limits: &Limits) -> Result<Vec<u8>, Error> {
+    if length > limits.max_body_size {
+        return Err(Error::TooLarge(Limit::Body));
+    }
+
+    let mut body: Vec<u8> = Vec::with_capacity(length.min(MAX_RESERVED) as usize);
+    reader.by_ref().take(length).read_to_end(&mut body)?;
+
+    if (body.len() as u64) < length {
+        return Err(Error::body("Unexpected end of body"));
+    }
 
-    let mut body: Vec<u8> = Vec::with_capacity(length);
-    reader.take(length as u64).read_to_end(&mut body)?;
     Ok(body)
 }
 
 /// Read a body using the Transfer-Encoding: chunked method
-pub fn read_transfer<T: Read>(transfer: String, reader: &mut BufReader<&mut T>) -> Result<Vec<u8>, Error> {
-    if transfer != "chunked" {
+pub fn read_transfer<R: BufRead>(transfer: String, reader: &mut R, limits: &Limits) -> Result<(Vec<u8>, HeaderSet), Error> {
+    if !transfer.trim().eq_ignore_ascii_case("chunked") {
         return Err(Error::header("Unsupported Transfer-Encoding"));
     }
 
+    let mut chunked = ChunkedReader::new(reader, limits);
     let mut body: Vec<u8> = Vec::new();
-    let mut line = String::new();
+    let mut buffer = [0u8; 8192];
 
     loop {
-        line.clear();
-        reader.read_line(&mut line)?;
-        let line = line.trim();
+        let size = chunked.read_chunk(&mut buffer)?;
+        if size == 0 {
+            return Ok((body, chunked.trailers));
+        }
+        body.extend_from_slice(&buffer[..size]);
+    }
+}
+
+/// Read a body that ends when the peer closes the connection. HTTP/1.0
+/// servers send responses of unknown length this way
+pub fn read_until_close<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, Error> {
+    let mut body: Vec<u8> = Vec::new();
+    reader.by_ref().take(limits.max_body_size + 1).read_to_end(&mut body)?;
+
+    if body.len() as u64 > limits.max_body_size {
+        return Err(Error::TooLarge(Limit::Body));
+    }
+
+    Ok(body)
+}
+
+/// Reader decoding a body sent with the Transfer-Encoding: chunked method,
+/// following RFC 9112 section 7.1:
+///
+/// ```text
+/// chunk-size [ ";" chunk-ext ] CRLF
+/// chunk-data CRLF
+/// ...
+/// "0" [ ";" chunk-ext ] CRLF
+/// trailer fields
+/// CRLF
+/// ```
+///
+/// Chunk extensions are ignored. Trailers are available once the whole body
+/// has been read. The size of the body and the trailers are limited.
+pub struct ChunkedReader<R> {
+    reader: R,
+    limits: Limits,
+    remaining: u64,
+    total: u64,
+    done: bool,
+    pub trailers: HeaderSet,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
 
-        if line == "" {
-            return Ok(body)
+    pub fn new(reader: R, limits: &Limits) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            limits: limits.clone(),
+            remaining: 0,
+            total: 0,
+            done: false,
+            trailers: HeaderSet::new(),
+        }
+    }
+
+    /// Get the reader back, e.g. once the whole body has been read
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read data from the current chunk, moving on to the next one when it's
+    /// exhausted. Returns 0 once the last chunk and the trailers have been read
+    pub fn read_chunk(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            let size = self.read_size()?;
+            if size == 0 {
+                self.trailers = HeaderSet::read(&mut self.reader, &self.limits)?;
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = size;
+        }
+
+        let max = self.remaining.min(buf.len() as u64) as usize;
+        let size = self.reader.read(&mut buf[..max])?;
+        if size == 0 {
+            return Err(Error::body("Unexpected end of chunk"));
+        }
+
+        self.remaining -= size as u64;
+        if self.remaining == 0 {
+            self.read_end_of_chunk()?;
+        }
+
+        Ok(size)
+    }
+
+    /// Read the line with the size of the next chunk
+    fn read_size(&mut self) -> Result<u64, Error> {
+        let mut line = String::new();
+        self.reader.by_ref().take(MAX_CHUNK_LINE).read_line(&mut line)?;
+
+        if !line.ends_with('\n') {
+            return Err(Error::body("Invalid chunk size line"));
+        }
+
+        // Extensions start with ';' and can be preceded by whitespace
+        let size = line
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim();
+
+        if size.is_empty() || !size.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::body("Invalid chunk size"));
+        }
+
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| Error::body("Chunk too large"))?;
+
+        if size > MAX_CHUNK_SIZE {
+            return Err(Error::body("Chunk too large"));
+        }
+
+        self.total += size;
+        if self.total > self.limits.max_body_size {
+            return Err(Error::TooLarge(Limit::Body));
         }
-        
-        let length = usize::from_str_radix(line, 16)
-            .map_err(|_| Error::header("Invalid Transfer-Encoding"))?;
 
-        reader.take(length as u64).read_to_end(&mut body)?;
+        Ok(size)
+    }
+
+    /// Read the CRLF that follows the data of every chunk
+    fn read_end_of_chunk(&mut self) -> Result<(), Error> {
+        let mut end = [0u8; 1];
+        self.reader.read_exact(&mut end)?;
+
+        // A bare LF is tolerated, as recommended by RFC 9112 section 2.2
+        if end[0] == b'\r' {
+            self.reader.read_exact(&mut end)?;
+        }
+
+        if end[0] != b'\n' {
+            return Err(Error::body("Missing CRLF after chunk"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_chunk(buf).map_err(|e| match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, format!("{}", e))
+        })
     }
 }
 
 /// Write a body to a TCP stream for a HTTP request or response
 pub fn write_body<T: Write>(body: &[u8], stream: &mut T) -> Result<(), Error> {
     let line = format!("Content-Length: {}\r\n\r\n", body.len());
-    stream.write(line.as_bytes())?;
-    stream.write(body)?;
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(body)?;
+
+    Ok(())
+}
+
+/// Write a body of unknown length to a TCP stream using the
+/// Transfer-Encoding: chunked method. Trailers are sent after the last chunk
+pub fn write_chunked<R: Read + ?Sized, T: Write>(reader: &mut R, trailers: &HeaderSet, stream: &mut T) -> Result<(), Error> {
+    stream.write_all(b"Transfer-Encoding: chunked\r\n")?;
+
+    if !trailers.is_empty() {
+        let names: Vec<&str> = trailers.iter().map(|h| h.key.as_str()).collect();
+        let line = format!("Trailer: {}\r\n", names.join(", "));
+        stream.write_all(line.as_bytes())?;
+    }
+    stream.write_all(b"\r\n")?;
+
+    let mut writer = ChunkedWriter::new(stream);
+    io::copy(reader, &mut writer)?;
+    writer.finish(trailers)
+}
 
+/// Write a body of unknown length to a peer that can't decode the chunked
+/// method, e.g. a HTTP/1.0 client. The body ends when the connection is closed
+pub fn write_until_close<R: Read + ?Sized, T: Write>(reader: &mut R, stream: &mut T) -> Result<(), Error> {
+    stream.write_all(b"\r\n")?;
+    io::copy(reader, stream)?;
+    stream.flush()?;
     Ok(())
 }
+
+/// Writer that sends everything written to it as chunks of a body using the
+/// Transfer-Encoding: chunked method. Every write is sent right away as its
+/// own chunk so that events can be streamed to the peer as they happen.
+/// `finish` must be called to send the last chunk.
+pub struct ChunkedWriter<'a, T: Write> {
+    stream: &'a mut T
+}
+
+impl<'a, T: Write> ChunkedWriter<'a, T> {
+
+    pub fn new(stream: &'a mut T) -> ChunkedWriter<'a, T> {
+        ChunkedWriter { stream }
+    }
+
+    /// Send the last chunk, followed by the trailers
+    pub fn finish(self, trailers: &HeaderSet) -> Result<(), Error> {
+        self.stream.write_all(b"0\r\n")?;
+        trailers.write(self.stream)?;
+        self.stream.write_all(b"\r\n")?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a, T: Write> Write for ChunkedWriter<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would mark the end of the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let size = format!("{:X}\r\n", buf.len());
+        self.stream.write_all(size.as_bytes())?;
+        self.stream.write_all(buf)?;
+        self.stream.write_all(b"\r\n")?;
+        self.stream.flush()?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Reader over an iterator of byte buffers, so that a body can be produced
+/// piece by piece, e.g. for server-sent events. Each buffer becomes a chunk
+/// when sent with the chunked method
+#[cfg(test)]
+pub struct ChunkReader<I, B> {
+    chunks: I,
+    current: Option<B>,
+    pos: usize,
+}
+
+#[cfg(test)]
+impl<I, B> ChunkReader<I, B> where I: Iterator<Item = B>, B: AsRef<[u8]> {
+
+    pub fn new(chunks: I) -> ChunkReader<I, B> {
+        ChunkReader {
+            chunks,
+            current: None,
+            pos: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+impl<I, B> Read for ChunkReader<I, B> where I: Iterator<Item = B>, B: AsRef<[u8]> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.current {
+                let remaining = &chunk.as_ref()[self.pos..];
+                if !remaining.is_empty() {
+                    let size = remaining.len().min(buf.len());
+                    buf[..size].copy_from_slice(&remaining[..size]);
+                    self.pos += size;
+                    return Ok(size);
+                }
+            }
+
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = Some(chunk);
+                    self.pos = 0;
+                },
+                None => return Ok(0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(data: &str, limits: &Limits) -> Result<(Vec<u8>, HeaderSet), Error> {
+        read_transfer("chunked".to_string(), &mut data.as_bytes(), limits)
+    }
+
+    #[test]
+    fn chunk_extensions_are_ignored() {
+        let data = "5;name=value\r\nhello\r\n6 ; quoted=\"a;b\"\r\n world\r\n0;last\r\n\r\n";
+        let (body, trailers) = chunked(data, &Limits::default()).unwrap();
+        assert_eq!(body, b"hello world");
+        assert!(trailers.get("name").is_none());
+    }
+
+    #[test]
+    fn trailers_follow_last_chunk() {
+        let data = "3\r\nabc\r\n0\r\nX-Checksum: 42\r\nExpires: never\r\n\r\n";
+        let (body, trailers) = chunked(data, &Limits::default()).unwrap();
+        assert_eq!(body, b"abc");
+        assert_eq!(trailers.get("x-checksum").as_deref(), Some("42"));
+        assert_eq!(trailers.get("Expires").as_deref(), Some("never"));
+    }
+
+    #[test]
+    fn bare_line_feeds_are_tolerated() {
+        let (body, _) = chunked("3\nabc\nA\n0123456789\n0\n\n", &Limits::default()).unwrap();
+        assert_eq!(body, b"abc0123456789");
+    }
+
+    #[test]
+    fn invalid_chunk_sizes() {
+        for data in &["\r\n", "-1\r\n", "0x3\r\nabc\r\n0\r\n\r\n", "g\r\n", "3 3\r\nabc\r\n", "3", "ffffffffffffffffff\r\n"] {
+            assert!(matches!(chunked(data, &Limits::default()), Err(Error::Body(_))), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn invalid_chunk_data() {
+        assert!(matches!(chunked("3\r\nabcd\r\n0\r\n\r\n", &Limits::default()), Err(Error::Body(_))));
+        assert!(chunked("5\r\nab", &Limits::default()).is_err());
+        assert!(chunked("3\r\nabc\r\n", &Limits::default()).is_err());
+    }
+
+    #[test]
+    fn chunks_are_limited() {
+        let limits = Limits { max_body_size: u64::MAX, ..Limits::default() };
+        let data = format!("{:x}\r\n", MAX_CHUNK_SIZE + 1);
+        assert!(matches!(chunked(&data, &limits), Err(Error::Body(_))));
+
+        let limits = Limits { max_body_size: 4, ..Limits::default() };
+        let data = "3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n";
+        assert!(matches!(chunked(data, &limits), Err(Error::TooLarge(Limit::Body))));
+    }
+
+    #[test]
+    fn chunked_reader_stops_at_end_of_body() {
+        let mut reader = ChunkedReader::new("3\r\nabc\r\n2\r\nde\r\n0\r\n\r\nGET / HTTP/1.1".as_bytes(), &Limits::default());
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "abcde");
+        assert_eq!(reader.into_inner(), b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn framing_from_headers() {
+        let limits = Limits::default();
+        let mut headers = HeaderSet::new();
+        assert_eq!(Framing::from_headers(&headers, &limits).unwrap(), Framing::Empty);
+
+        headers.add("Content-Length", "12");
+        assert_eq!(Framing::from_headers(&headers, &limits).unwrap(), Framing::Length(12));
+        assert!(!Framing::is_ambiguous(&headers));
+
+        headers.add("Transfer-Encoding", "chunked");
+        assert_eq!(Framing::from_headers(&headers, &limits).unwrap(), Framing::Chunked);
+        assert!(Framing::is_ambiguous(&headers));
+
+        headers.set("Transfer-Encoding", "gzip, chunked");
+        assert!(Framing::from_headers(&headers, &limits).is_err());
+    }
+}
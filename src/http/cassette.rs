@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::http::{Error, Request, Response, RetryPolicy, StatusCode, Transport, Url, Verb};
+use crate::http::base64;
+use crate::json;
+use crate::json::JsonType;
+
+/// Saved instead of the value of an ignored argument or a sensitive header
+const HIDDEN: &str = "<hidden>";
+
+/// Headers whose values are never saved since they hold credentials
+const SENSITIVE_HEADERS: [&str; 4] = ["Authorization", "Cookie", "Proxy-Authorization", "Set-Cookie"];
+
+/// A request that was sent and the response received for it
+struct Interaction {
+    verb: Verb,
+    url: Url,
+    request_headers: Vec<(String, String)>,
+    request_body: Vec<u8>,
+    code: StatusCode,
+    response_headers: Vec<(String, String)>,
+    response_body: Vec<u8>,
+    /// Already served back when replaying
+    used: bool,
+}
+
+enum Mode {
+    /// Send requests with a transport and save them with their responses
+    Record(Box<dyn Transport>),
+    /// Answer requests with the saved responses
+    Replay,
+}
+
+/// A transport that saves HTTP traffic to a JSON file, or serves it back
+/// without touching the network.
+///
+/// When replaying, a request gets the response of the first unused
+/// interaction with the same verb and URL, so that responses are served in
+/// the order they were recorded. Once they are all used, the last one is
+/// repeated. Requests that were never recorded fail like a connection that
+/// was refused
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    ignored_args: Vec<String>,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+
+    /// Record the requests sent with a transport and their responses. The
+    /// file is rewritten after every response
+    pub fn record<P: AsRef<Path>>(path: P, transport: Box<dyn Transport>) -> Cassette {
+        Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: Mode::Record(transport),
+            ignored_args: Vec::new(),
+            interactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Replay the responses recorded in a file
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Cassette, Error> {
+        let text = fs::read_to_string(path.as_ref())?;
+        let cassette = json::parse(&text).map_err(|e| invalid(&e.to_string()))?;
+
+        let interactions = match field(&cassette, "interactions")? {
+            JsonType::Array(items) => items.iter()
+                .map(Interaction::from_json)
+                .collect::<Result<Vec<Interaction>, Error>>()?,
+            _ => return Err(invalid("'interactions' must be an array"))
+        };
+
+        Ok(Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: Mode::Replay,
+            ignored_args: Vec::new(),
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// Neither save nor compare the value of a query argument, e.g. a
+    /// password or today's date
+    pub fn ignore_arg(mut self, name: &str) -> Cassette {
+        self.ignored_args.push(name.to_string());
+        self
+    }
+
+    /// Copy a URL without the values of ignored arguments, its credentials
+    /// and its fragment
+    fn hide(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        url.userinfo = None;
+        url.fragment = None;
+
+        for name in self.ignored_args.iter() {
+            url.replace_arg(name, HIDDEN);
+        }
+
+        url
+    }
+
+    /// Lock the interactions, even if a thread panicked while holding them
+    fn interactions(&self) -> MutexGuard<'_, Vec<Interaction>> {
+        match self.interactions.lock() {
+            Ok(i) => i,
+            Err(e) => e.into_inner()
+        }
+    }
+
+    /// Send a request with the transport and save it with its response
+    fn record_send(&self, transport: &dyn Transport, mut request: Request, policy: Option<&RetryPolicy>)
+        -> Result<Response, Error>
+    {
+        // Streamed bodies are buffered so that they can be saved
+        let request_body = request.body.buffer()?.to_vec();
+
+        let verb = request.verb;
+        let url = self.hide(&request.url);
+        let request_headers = hide_headers(request.headers.iter().map(|h| (h.key.clone(), h.value.clone())));
+
+        let mut response = match policy {
+            Some(p) => transport.send_with_retry(request, p)?,
+            None => transport.send(request)?
+        };
+
+        let response_body = response.body.buffer()?.to_vec();
+
+        let mut interactions = self.interactions();
+        interactions.push(Interaction {
+            verb,
+            url,
+            request_headers,
+            request_body,
+            code: response.code,
+            response_headers: hide_headers(response.headers.iter().map(|h| (h.key.clone(), h.value.clone()))),
+            response_body,
+            used: false,
+        });
+
+        // One interaction per line keeps the file readable in a diff
+        let lines: Vec<String> = interactions.iter().map(|i| i.to_json().to_string()).collect();
+        fs::write(&self.path, format!("{{\"interactions\":[\n{}\n]}}\n", lines.join(",\n")))?;
+
+        Ok(response)
+    }
+
+    /// Answer a request with the response recorded for it
+    fn replay_send(&self, request: Request) -> Result<Response, Error> {
+        let url = self.hide(&request.url).to_string();
+        let mut interactions = self.interactions();
+
+        let matching: Vec<usize> = interactions.iter()
+            .enumerate()
+            .filter(|(_, i)| i.verb == request.verb && i.url.to_string() == url)
+            .map(|(n, _)| n)
+            .collect();
+
+        let position = matching.iter()
+            .find(|n| !interactions[**n].used)
+            .or(matching.last());
+
+        let interaction = match position {
+            Some(n) => &mut interactions[*n],
+            None => {
+                let message = format!("no recorded response for {} {}", request.verb.format(), url);
+                return Err(Error::from(io::Error::new(io::ErrorKind::ConnectionRefused, message)));
+            }
+        };
+
+        interaction.used = true;
+
+        let mut response = Response::new(interaction.code, &interaction.response_body);
+        for (name, value) in interaction.response_headers.iter() {
+            response.headers.add(name, value);
+        }
+
+        Ok(response)
+    }
+}
+
+impl Transport for Cassette {
+
+    fn send(&self, request: Request) -> Result<Response, Error> {
+        match &self.mode {
+            Mode::Record(transport) => self.record_send(transport.as_ref(), request, None),
+            Mode::Replay => self.replay_send(request)
+        }
+    }
+
+    fn send_with_retry(&self, request: Request, policy: &RetryPolicy) -> Result<Response, Error> {
+        match &self.mode {
+            Mode::Record(transport) => self.record_send(transport.as_ref(), request, Some(policy)),
+            Mode::Replay => self.replay_send(request)
+        }
+    }
+}
+
+impl Interaction {
+
+    /// Convert the interaction to the JSON saved in a cassette
+    fn to_json(&self) -> JsonType {
+        let request = object(vec![
+            ("verb", JsonType::String(self.verb.format().to_string())),
+            ("url", JsonType::String(self.url.to_string())),
+            ("headers", headers_to_json(&self.request_headers)),
+            body_to_json(&self.request_body),
+        ]);
+
+        let response = object(vec![
+            ("status", JsonType::Int(self.code.as_u16() as i64)),
+            ("headers", headers_to_json(&self.response_headers)),
+            body_to_json(&self.response_body),
+        ]);
+
+        object(vec![("request", request), ("response", response)])
+    }
+
+    /// Convert an interaction saved in a cassette
+    fn from_json(value: &JsonType) -> Result<Interaction, Error> {
+        let request = field(value, "request")?;
+        let response = field(value, "response")?;
+
+        let code = match field(response, "status")? {
+            JsonType::Int(c) if (0..=u16::MAX as i64).contains(c) => StatusCode::new(*c as u16)?,
+            _ => return Err(invalid("'status' must be a status code"))
+        };
+
+        Ok(Interaction {
+            verb: Verb::parse(string(field(request, "verb")?)?)?,
+            url: Url::parse(string(field(request, "url")?)?)?,
+            request_headers: headers_from_json(field(request, "headers")?)?,
+            request_body: body_from_json(request)?,
+            code,
+            response_headers: headers_from_json(field(response, "headers")?)?,
+            response_body: body_from_json(response)?,
+            used: false,
+        })
+    }
+}
+
+/// Replace the values of sensitive headers
+fn hide_headers<I: Iterator<Item=(String, String)>>(headers: I) -> Vec<(String, String)> {
+    headers
+        .map(|(name, value)| {
+            if SENSITIVE_HEADERS.iter().any(|s| s.eq_ignore_ascii_case(&name)) {
+                (name, HIDDEN.to_string())
+            } else {
+                (name, value)
+            }
+        })
+        .collect()
+}
+
+fn object(fields: Vec<(&str, JsonType)>) -> JsonType {
+    JsonType::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect::<HashMap<String, JsonType>>())
+}
+
+/// Headers are saved as an array of [name, value] pairs to keep their order
+fn headers_to_json(headers: &[(String, String)]) -> JsonType {
+    JsonType::Array(headers.iter()
+        .map(|(name, value)| JsonType::Array(vec![
+            JsonType::String(name.clone()),
+            JsonType::String(value.clone()),
+        ]))
+        .collect())
+}
+
+/// Text bodies are saved as-is, binary bodies are encoded with base64
+fn body_to_json(body: &[u8]) -> (&'static str, JsonType) {
+    match std::str::from_utf8(body) {
+        Ok(text) => ("body", JsonType::String(text.to_string())),
+        Err(_) => ("body_base64", JsonType::String(base64::encode(body)))
+    }
+}
+
+fn headers_from_json(value: &JsonType) -> Result<Vec<(String, String)>, Error> {
+    let items = match value {
+        JsonType::Array(items) => items,
+        _ => return Err(invalid("headers must be an array"))
+    };
+
+    items.iter()
+        .map(|item| match item {
+            JsonType::Array(pair) if pair.len() == 2 => {
+                Ok((string(&pair[0])?.to_string(), string(&pair[1])?.to_string()))
+            },
+            _ => Err(invalid("headers must be [name, value] pairs"))
+        })
+        .collect()
+}
+
+fn body_from_json(value: &JsonType) -> Result<Vec<u8>, Error> {
+    if let Ok(body) = field(value, "body") {
+        return Ok(string(body)?.as_bytes().to_vec());
+    }
+
+    base64::decode(string(field(value, "body_base64")?)?)
+        .ok_or(invalid("'body_base64' is not base64"))
+}
+
+/// Get a field of a JSON object
+fn field<'a>(value: &'a JsonType, name: &str) -> Result<&'a JsonType, Error> {
+    match value {
+        JsonType::Object(o) => o.get(name).ok_or(invalid(&format!("missing '{}'", name))),
+        _ => Err(invalid(&format!("expecting an object with '{}'", name)))
+    }
+}
+
+fn string(value: &JsonType) -> Result<&str, Error> {
+    match value {
+        JsonType::String(s) => Ok(s),
+        _ => Err(invalid("expecting a string"))
+    }
+}
+
+/// A cassette file that can't be understood
+fn invalid(message: &str) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, format!("invalid cassette: {}", message)))
+}
@@ -1,36 +1,509 @@
 extern crate native_tls;
 
-use std::net::TcpStream;
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::http::{Request, Response, Error, URL, Protocol};
-use crate::http::io::ReadWrite;
+use crate::http::{Body, CookieJar, Request, Response, Error, Limits, Url, Protocol, Verb, RetryPolicy, StatusCode, TlsConfig, ProxyConfig};
+use crate::http::encoding;
+use crate::http::io;
+use crate::http::proxy;
+use crate::http::io::{ReadWrite, TimedStream};
 use native_tls::TlsConnector;
 
+/// Maximum number of idle connections kept open for a single host
+const MAX_IDLE: usize = 4;
 
-/// Send an HTTP request and read the HTTP response
-pub fn send(mut request: Request) -> Result<Response, Error> {
-    request.headers.add_default("Host", &request.url.host);
-    request.headers.add_default("User-Agent", "rtcsms");
-    request.headers.add_default("Accept", "*/*");
+/// Idle connections, by protocol, host and port
+type Pool = Mutex<HashMap<String, Vec<Box<dyn ReadWrite>>>>;
 
-    let mut stream = connect(&request.url)?;
+/// Headers that must not be sent to another host when following a redirect
+const SENSITIVE_HEADERS: [&str; 3] = ["Authorization", "Cookie", "Proxy-Authorization"];
 
-    request.write(&mut stream)?;
-    Response::read(&mut stream)
+/// Settings for the HTTP client
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Number of redirects followed before giving up. Use 0 to return
+    /// redirect responses as-is
+    pub max_redirects: u32,
+    /// How long to wait for the TCP connection to be established
+    pub connect_timeout: Option<Duration>,
+    /// How long a single read from the server can block
+    pub read_timeout: Option<Duration>,
+    /// How long a single write to the server can block
+    pub write_timeout: Option<Duration>,
+    /// How long a request can take from start to finish, including redirects
+    pub timeout: Option<Duration>,
+    /// Largest responses accepted from servers
+    pub limits: Limits,
+    /// Proxies to send requests through. Read from the environment by default
+    pub proxy: ProxyConfig,
+    /// Keep the cookies set by servers in a jar and send them back with the
+    /// requests they match. The jar then replaces the 'Cookie' header of
+    /// requests
+    pub cookies: bool,
+    /// Ask servers for compressed responses. A compressed body is received
+    /// whole before it's decoded, so it can't be streamed
+    pub compressed: bool,
 }
 
-/// Connect to a server using a TCP or TLS-over-TCP socket
-pub fn connect(url: &URL) -> Result<Box<dyn ReadWrite>, Error> {
-    match url.protocol {
-        Protocol::Http => {
-            let stream = TcpStream::connect(url.connection())?;
-            return Ok(Box::new(stream));
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            max_redirects: 10,
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            timeout: None,
+            limits: Limits::default(),
+            proxy: ProxyConfig::from_env(),
+            cookies: false,
+            compressed: false,
+        }
+    }
+}
+
+/// HTTP client that follows redirects and keeps connections open between
+/// requests to the same host. Response bodies are read while they are used,
+/// and a connection can only be reused once its response's body has been read
+pub struct Client {
+    config: ClientConfig,
+    pool: Arc<Pool>,
+    /// Built on the first HTTPS connection unless given a TlsConfig
+    connector: Mutex<Option<TlsConnector>>,
+    server_name: Option<String>,
+    cookies: Option<CookieJar>,
+}
+
+impl Client {
+
+    pub fn with_config(config: ClientConfig) -> Client {
+        let cookies = match config.cookies {
+            true => Some(CookieJar::new()),
+            false => None
+        };
+
+        Client {
+            config,
+            pool: Arc::new(Mutex::new(HashMap::new())),
+            connector: Mutex::new(None),
+            server_name: None,
+            cookies,
+        }
+    }
+
+    /// Create a client with custom TLS settings, e.g. a private CA
+    #[cfg(test)]
+    pub fn with_tls(config: ClientConfig, mut tls: TlsConfig) -> Result<Client, Error> {
+        let mut client = Client::with_config(config);
+        client.server_name = tls.server_name.take();
+        client.connector = Mutex::new(Some(tls.connector()?));
+        Ok(client)
+    }
+
+    /// Send an HTTP request and read the HTTP response, following redirects
+    pub fn send(&self, mut request: Request) -> Result<Response, Error> {
+        let deadline = self.config.timeout.map(|t| Instant::now() + t);
+        self.send_redirects(&mut request, deadline)
+    }
+
+    /// Send an HTTP request, retrying transient failures according to the
+    /// policy. The last response or error is returned when all attempts
+    /// failed. The client's timeout covers all attempts and delays. Requests
+    /// with a streamed body are never retried since the body can't be sent twice
+    pub fn send_with_retry(&self, mut request: Request, policy: &RetryPolicy) -> Result<Response, Error> {
+        let deadline = self.config.timeout.map(|t| Instant::now() + t);
+        let streamed = request.body.is_stream();
+        let mut attempt = 1;
+
+        loop {
+            let result = self.send_redirects(&mut request, deadline);
+
+            if attempt >= policy.max_attempts
+                || streamed
+                || !policy.applies_to(request.verb)
+                || !policy.should_retry(&result)
+            {
+                return result;
+            }
+
+            let delay = policy.delay(attempt, &result);
+            if let Some(d) = deadline {
+                if Instant::now() + delay >= d {
+                    return result;
+                }
+            }
+
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Send an HTTP request and follow redirects until the final response
+    fn send_redirects(&self, request: &mut Request, deadline: Option<Instant>) -> Result<Response, Error> {
+        let mut redirects = 0;
+
+        loop {
+            let streamed = request.body.is_stream();
+            let response = self.send_once(request, deadline)?;
+
+            // A redirect can set cookies for its location
+            if let Some(jar) = &self.cookies {
+                jar.store(&request.url, &response);
+            }
+
+            // A streamed body can't be sent again to the new location
+            let follow = self.config.max_redirects > 0
+                && Client::is_redirect(response.code)
+                && (!streamed || Client::switches_to_get(request.verb, response.code));
+            let location = match response.headers.get("Location") {
+                Some(l) if follow => l,
+                _ => return Ok(response)
+            };
+
+            if redirects >= self.config.max_redirects {
+                return Err(Error::TooManyRedirects);
+            }
+            redirects += 1;
+
+            Client::redirect(request, response.code, &location)?;
         }
-        Protocol::Https => {
-            let connector = TlsConnector::new()?;
-            let stream = TcpStream::connect(url.connection())?;
-            let stream = connector.connect(&url.host, stream)?;
+    }
+
+    /// Check if a status code is a redirect the client can follow
+    fn is_redirect(code: StatusCode) -> bool {
+        matches!(
+            code,
+            StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT
+        )
+    }
+
+    /// Check if a redirect changes the request to a GET without a body.
+    /// 303 always means 'GET the result somewhere else'. Browsers also switch
+    /// POST to GET for 301 and 302, and servers expect it. 307 and 308 keep
+    /// the same verb and body
+    fn switches_to_get(verb: Verb, code: StatusCode) -> bool {
+        match code {
+            StatusCode::SEE_OTHER => verb != Verb::Head,
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => verb == Verb::Post,
+            _ => false
+        }
+    }
+
+    /// Change a request so that it can be sent to the location of a redirect
+    fn redirect(request: &mut Request, code: StatusCode, location: &str) -> Result<(), Error> {
+        let url = request.url.join(location)?;
+
+        if Client::switches_to_get(request.verb, code) {
+            request.verb = Verb::Get;
+            request.body = Body::empty();
+            request.headers.remove("Content-Length");
+            request.headers.remove("Content-Type");
+            request.headers.remove("Transfer-Encoding");
+        }
+
+        let same_origin = url.protocol == request.url.protocol
+            && url.host == request.url.host
+            && url.port == request.url.port;
+
+        if !same_origin {
+            for header in SENSITIVE_HEADERS.iter() {
+                request.headers.remove(header);
+            }
+        }
+
+        request.headers.remove("Host");
+        request.url = url;
+
+        Ok(())
+    }
+
+    /// Send an HTTP request and read the HTTP response, reusing an idle
+    /// connection to the host if one is available
+    fn send_once(&self, request: &mut Request, deadline: Option<Instant>) -> Result<Response, Error> {
+        request.headers.add_default("Host", &request.url.authority());
+        request.headers.add_default("User-Agent", "rtcsms");
+        request.headers.add_default("Accept", "*/*");
+        if self.config.compressed {
+            request.headers.add_default("Accept-Encoding", encoding::ACCEPTED);
+        }
+
+        if let Some(credentials) = self.forwarded_by(&request.url).and_then(proxy::authorization) {
+            request.headers.add_default("Proxy-Authorization", &credentials);
+        }
+
+        // The cookies matching a redirect's location can be different
+        if let Some(jar) = &self.cookies {
+            match jar.header(&request.url) {
+                Some(cookies) => request.headers.set("Cookie", &cookies),
+                None => request.headers.remove("Cookie")
+            }
+        }
+
+        // The server may have closed an idle connection since it was last
+        // used. Requests that are not idempotent are never sent on an idle
+        // connection since we couldn't tell if the server received them
+        // before failing. A timeout isn't retried, the server is just slow.
+        // A streamed body can only be sent once, so always use a new connection.
+        if request.verb.is_idempotent() && !request.body.is_stream() {
+            if let Some(stream) = self.checkout(&request.url) {
+                match self.exchange(stream, request, deadline) {
+                    Ok(response) => return Ok(response),
+                    Err(Error::Timeout) => return Err(Error::Timeout),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        let connect_timeout = io::until(self.config.connect_timeout, deadline)?;
+        let stream = self.connect(&request.url, connect_timeout)?;
+        self.exchange(stream, request, deadline)
+    }
+
+    /// Write a request and read its response on a connection. The connection
+    /// goes back to the pool once the response's body has been read
+    fn exchange(&self, stream: Box<dyn ReadWrite>, request: &mut Request, deadline: Option<Instant>)
+        -> Result<Response, Error>
+    {
+        let mut stream = TimedStream::new(stream, self.config.read_timeout, self.config.write_timeout);
+        stream.set_deadline(deadline);
+
+        if self.forwarded_by(&request.url).is_some() {
+            request.write_proxied(&mut stream)?;
+        } else {
+            request.write(&mut stream)?;
+        }
+
+        let pool = self.pool.clone();
+        let key = self.pool_key(&request.url);
+        Response::read_streamed(BufReader::new(stream), request.verb, &self.config.limits, move |reader| {
+            Client::checkin(&pool, &key, reader.into_inner().into_inner());
+        })
+    }
+
+    /// Connect to a server using a TCP or TLS-over-TCP socket. The timeout also
+    /// limits how long the TLS handshake can block
+    fn connect(&self, url: &Url, timeout: Option<Duration>) -> Result<Box<dyn ReadWrite>, Error> {
+        let proxy = self.config.proxy.for_url(url);
+        let mut stream = connect_tcp(proxy.unwrap_or(url), timeout)?;
+
+        if url.protocol == Protocol::Http {
             return Ok(Box::new(stream));
         }
+
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+
+        if let Some(proxy) = proxy {
+            self.tunnel(&mut stream, url, proxy)?;
+        }
+
+        let name = self.server_name.as_ref().unwrap_or(&url.host);
+        let stream = self.connector()?.connect(name, stream)?;
+        Ok(Box::new(stream))
+    }
+
+    /// Ask a proxy to open a tunnel to the URL's host with CONNECT. Once
+    /// opened, the TLS handshake and the requests go through the tunnel
+    fn tunnel(&self, stream: &mut TcpStream, url: &Url, proxy: &Url) -> Result<(), Error> {
+        let mut request = Request::new(Verb::Connect, url.clone());
+        request.headers.add("Host", &url.connection());
+        if let Some(credentials) = proxy::authorization(proxy) {
+            request.headers.add("Proxy-Authorization", &credentials);
+        }
+        request.write(stream)?;
+
+        // Read one byte at a time so that nothing sent through the tunnel
+        // is buffered along with the response
+        let mut reader = BufReader::with_capacity(1, stream);
+        let response = Response::read_head(&mut reader, &self.config.limits)?;
+
+        if !response.code.is_success() {
+            return Err(Error::Proxy(response.code));
+        }
+
+        Ok(())
+    }
+
+    /// Get the proxy that forwards requests to a URL, if any. HTTPS requests
+    /// go through a tunnel instead, so the proxy never sees them
+    fn forwarded_by(&self, url: &Url) -> Option<&Url> {
+        match url.protocol {
+            Protocol::Http => self.config.proxy.for_url(url),
+            Protocol::Https => None,
+        }
+    }
+
+    /// Get the connector shared by all HTTPS connections, building one with
+    /// the default settings the first time
+    fn connector(&self) -> Result<TlsConnector, Error> {
+        let mut connector = match self.connector.lock() {
+            Ok(c) => c,
+            Err(e) => e.into_inner()
+        };
+
+        if let Some(c) = connector.as_ref() {
+            return Ok(c.clone());
+        }
+
+        let c = TlsConfig::default().connector()?;
+        *connector = Some(c.clone());
+        Ok(c)
+    }
+
+    /// Take an idle connection to the URL's host out of the pool
+    fn checkout(&self, url: &Url) -> Option<Box<dyn ReadWrite>> {
+        let mut pool = self.pool.lock().ok()?;
+        pool.get_mut(&self.pool_key(url))?.pop()
+    }
+
+    /// Put a connection the server is keeping open back in the pool
+    fn checkin(pool: &Pool, key: &str, stream: Box<dyn ReadWrite>) {
+        if let Ok(mut pool) = pool.lock() {
+            let idle = pool.entry(key.to_string()).or_insert_with(Vec::new);
+            if idle.len() < MAX_IDLE {
+                idle.push(stream);
+            }
+        }
+    }
+
+    /// Connections can only be reused for the same protocol, host and port.
+    /// A connection to a proxy forwarding requests can be used for any host
+    fn pool_key(&self, url: &Url) -> String {
+        match self.forwarded_by(url) {
+            Some(proxy) => format!("proxy://{}", proxy.connection()),
+            None => format!("{}://{}", url.protocol.scheme(), url.connection())
+        }
+    }
+}
+
+/// Open a TCP socket to the host, trying every address it resolves to
+fn connect_tcp(url: &Url, timeout: Option<Duration>) -> Result<TcpStream, Error> {
+    let timeout = match timeout {
+        Some(t) => t,
+        None => return TcpStream::connect(url.connection()).map_err(Error::Connect)
     };
+
+    let mut error = Error::url("Host has no address");
+    for address in url.connection().to_socket_addrs().map_err(Error::Connect)? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(s) => return Ok(s),
+            Err(e) => error = Error::Connect(e)
+        }
+    }
+
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use super::*;
+
+    /// Accept one connection, answer it with a response and send back the
+    /// head of the request that was received
+    fn fake_proxy(response: &'static str) -> (Url, Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                head.push(line.trim_end().to_string());
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            sender.send(head).unwrap();
+        });
+
+        (proxy, receiver)
+    }
+
+    fn client(proxy: Url) -> Client {
+        Client::with_config(ClientConfig {
+            proxy: ProxyConfig::all(proxy),
+            ..ClientConfig::default()
+        })
+    }
+
+    #[test]
+    fn http_requests_use_the_absolute_form() {
+        let (mut proxy, head) = fake_proxy("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        proxy.userinfo = Some("user:secret".to_string());
+
+        let url = Url::parse("http://example.com:8080/stops?stop=1234#top").unwrap();
+        let mut response = client(proxy).send(Request::new(Verb::Get, url)).unwrap();
+        assert_eq!(response.body_string().unwrap(), "ok");
+
+        let head = head.recv().unwrap();
+        assert_eq!(head[0], "GET http://example.com:8080/stops?stop=1234 HTTP/1.1");
+        assert!(head.contains(&"Host: example.com:8080".to_string()));
+        assert!(head.contains(&"Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=".to_string()));
+    }
+
+    #[test]
+    fn compressed_responses_are_opt_in() {
+        let url = Url::parse("http://example.com/stops").unwrap();
+
+        let (proxy, head) = fake_proxy("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        client(proxy).send(Request::new(Verb::Get, url.clone())).unwrap();
+        assert!(!head.recv().unwrap().iter().any(|h| h.starts_with("Accept-Encoding")));
+
+        let (proxy, head) = fake_proxy("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let config = ClientConfig { proxy: ProxyConfig::all(proxy), compressed: true, ..ClientConfig::default() };
+        Client::with_config(config).send(Request::new(Verb::Get, url)).unwrap();
+        assert!(head.recv().unwrap().contains(&"Accept-Encoding: gzip, deflate".to_string()));
+    }
+
+    #[test]
+    fn https_requests_go_through_a_tunnel() {
+        let (mut proxy, head) = fake_proxy("HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n");
+        proxy.userinfo = Some("user:secret".to_string());
+
+        let url = Url::parse("https://example.com/stops?stop=1234").unwrap();
+        match client(proxy).send(Request::new(Verb::Get, url)) {
+            Err(Error::Proxy(code)) => assert_eq!(code.as_u16(), 407),
+            other => panic!("expected the proxy to refuse the tunnel, got {:?}", other),
+        }
+
+        // Only the host and port are sent to the proxy, never the path
+        let head = head.recv().unwrap();
+        assert_eq!(head[0], "CONNECT example.com:443 HTTP/1.1");
+        assert!(head.contains(&"Host: example.com:443".to_string()));
+        assert!(head.contains(&"Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=".to_string()));
+    }
+
+    #[test]
+    fn no_proxy_hosts_are_reached_directly() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            assert_eq!(line, "GET / HTTP/1.1\r\n");
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        });
+
+        let mut config = ProxyConfig::all(Url::parse("http://127.0.0.2:9").unwrap());
+        config.no_proxy = vec!["127.0.0.1".to_string()];
+        let client = Client::with_config(ClientConfig { proxy: config, ..ClientConfig::default() });
+        assert_eq!(client.send(Request::new(Verb::Get, url)).unwrap().code, StatusCode::NO_CONTENT);
+    }
 }
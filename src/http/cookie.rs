@@ -0,0 +1,507 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+use crate::http::{Error, Protocol, Response, Url};
+use crate::http::date;
+
+/// Format of 'Expires' used by many servers, e.g. 'Wed, 21-Oct-2015 07:28:00 GMT'
+const NETSCAPE_DATE: &str = "%a, %d-%b-%Y %H:%M:%S GMT";
+
+/// Longest a cookie is kept, following RFC 6265bis section 5.6.2
+const MAX_AGE_LIMIT: i64 = 400 * 24 * 60 * 60;
+
+/// When a cookie is sent along with requests coming from other sites
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+
+    pub fn format(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie, as set by a server with 'Set-Cookie' or sent back by a client
+/// with 'Cookie'. Only the name and value are sent back, the attributes tell
+/// the client when to do it. Following RFC 6265, invalid attributes are
+/// ignored.
+///
+/// A server sets a cookie by building one and adding it to its response:
+/// `Cookie::new("session", "abc").path("/").max_age(3600).http_only()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub expires: Option<DateTime<Utc>>,
+    /// Number of seconds until the cookie expires, has priority over 'expires'
+    pub max_age: Option<i64>,
+    /// Domain whose subdomains also get the cookie. Without it, only the
+    /// host that set the cookie gets it
+    pub domain: Option<String>,
+    /// Only paths under this one get the cookie
+    pub path: Option<String>,
+    /// Only send the cookie over HTTPS
+    pub secure: bool,
+    /// Hide the cookie from scripts in browsers
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn max_age(mut self, seconds: i64) -> Cookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn http_only(mut self) -> Cookie {
+        self.http_only = true;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Parse the value of a 'Set-Cookie' header, following RFC 6265 section 5.2
+    pub fn parse(text: &str) -> Result<Cookie, Error> {
+        let mut parts = text.split(';');
+
+        let (name, value) = split_pair(parts.next().unwrap_or(""))
+            .ok_or(Error::header("Invalid Set-Cookie"))?;
+        let mut cookie = Cookie::new(name, value);
+
+        for attribute in parts {
+            let mut attribute = attribute.splitn(2, '=');
+            let name = attribute.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = attribute.next().unwrap_or("").trim();
+
+            match name.as_str() {
+                "expires" => {
+                    if let Some(date) = parse_date(value) {
+                        cookie.expires = Some(date);
+                    }
+                },
+                "max-age" => {
+                    let negative = value.starts_with('-');
+                    let digits = if negative { &value[1..] } else { value };
+                    if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                        // Too many digits for an i64 is still a long time, or
+                        // long ago
+                        let seconds = value.parse::<i64>()
+                            .unwrap_or(if negative { -1 } else { MAX_AGE_LIMIT });
+                        cookie.max_age = Some(seconds);
+                    }
+                },
+                "domain" => {
+                    // A leading dot is obsolete and means the same thing
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain.is_empty() {
+                        cookie.domain = Some(domain);
+                    }
+                },
+                "path" if value.starts_with('/') => cookie.path = Some(value.to_string()),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => {
+                    cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                        "strict" => Some(SameSite::Strict),
+                        "lax" => Some(SameSite::Lax),
+                        "none" => Some(SameSite::None),
+                        _ => cookie.same_site
+                    };
+                },
+                _ => {}
+            }
+        }
+
+        Ok(cookie)
+    }
+
+    /// Check that the cookie can be sent in a 'Set-Cookie' header: the name
+    /// must be a token, and the value and attributes can't contain ';' or
+    /// control characters, which would let them add attributes or headers.
+    /// The value follows RFC 6265 section 4.1.1
+    #[cfg(test)]
+    pub fn validate(&self) -> Result<(), Error> {
+        if !crate::http::media::is_token(&self.name) {
+            return Err(Error::header("Invalid cookie name"));
+        }
+
+        let value = match self.value.len() > 1 && self.value.starts_with('"') && self.value.ends_with('"') {
+            true => &self.value[1..self.value.len() - 1],
+            false => self.value.as_str()
+        };
+        if !value.chars().all(is_cookie_char) {
+            return Err(Error::header("Invalid cookie value"));
+        }
+
+        let attributes = self.domain.iter().chain(self.path.iter());
+        for attribute in attributes {
+            if attribute.chars().any(|c| c == ';' || c.is_control()) {
+                return Err(Error::header("Invalid cookie attribute"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// When the cookie expires, if it isn't deleted at the end of the
+    /// session. A cookie with a Max-Age of 0 or less is already expired
+    fn expiry(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.max_age {
+            Some(seconds) => Some(now + Duration::seconds(seconds.clamp(-1, MAX_AGE_LIMIT))),
+            None => self.expires.map(|e| e.min(now + Duration::seconds(MAX_AGE_LIMIT)))
+        }
+    }
+}
+
+/// Writes the cookie as the value of a 'Set-Cookie' header. Nothing is
+/// escaped, use `validate` first
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(expires) = &self.expires {
+            write!(f, "; Expires={}", date::format(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.format())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A cookie kept by a client, with where it's sent back
+struct Stored {
+    cookie: Cookie,
+    domain: String,
+    /// Only the host that set the cookie gets it, not its subdomains
+    host_only: bool,
+    path: String,
+    expiry: Option<DateTime<Utc>>,
+    /// Order in which cookies were first stored, to send older ones first
+    created: u64,
+}
+
+/// Cookies received by a client, sent back with the requests they match.
+/// Follows RFC 6265 sections 5.3 and 5.4, except that there is no list of
+/// public suffixes: a server can't set a cookie for a top-level domain, but
+/// can for e.g. 'co.uk'
+pub struct CookieJar {
+    cookies: Mutex<Vec<Stored>>,
+    created: Mutex<u64>,
+}
+
+impl CookieJar {
+
+    pub fn new() -> CookieJar {
+        CookieJar {
+            cookies: Mutex::new(Vec::new()),
+            created: Mutex::new(0),
+        }
+    }
+
+    /// Keep the cookies set by a response to a request sent to a URL
+    pub fn store(&self, url: &Url, response: &Response) {
+        for cookie in response.cookies() {
+            self.add(url, cookie);
+        }
+    }
+
+    /// Keep a cookie as if it was set by a response from a URL. A cookie the
+    /// URL isn't allowed to set is ignored. An expired cookie deletes the one
+    /// with the same name, domain and path
+    pub fn add(&self, url: &Url, cookie: Cookie) {
+        let host = url.host.to_ascii_lowercase();
+
+        let (domain, host_only) = match &cookie.domain {
+            Some(d) if !domain_matches(&host, d) => return,
+            Some(d) if !d.contains('.') && *d != host => return,
+            Some(d) => (d.clone(), false),
+            None => (host, true)
+        };
+
+        // Only a secure site can set a cookie that is only sent securely
+        if cookie.secure && url.protocol != Protocol::Https {
+            return;
+        }
+
+        let path = match &cookie.path {
+            Some(p) => p.clone(),
+            None => default_path(&url.path)
+        };
+
+        let now = Utc::now();
+        let expiry = cookie.expiry(now);
+
+        let mut cookies = self.lock();
+        let existing = cookies.iter()
+            .position(|s| s.cookie.name == cookie.name && s.domain == domain && s.path == path);
+        let created = match existing {
+            Some(n) => cookies.remove(n).created,
+            None => self.next_created()
+        };
+
+        if expiry.map(|e| e <= now).unwrap_or(false) {
+            return;
+        }
+
+        cookies.push(Stored {
+            cookie,
+            domain,
+            host_only,
+            path,
+            expiry,
+            created,
+        });
+    }
+
+    /// Build the value of the 'Cookie' header for a request sent to a URL.
+    /// Cookies with longer paths come first, then older ones
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let host = url.host.to_ascii_lowercase();
+        let now = Utc::now();
+
+        let mut cookies = self.lock();
+        cookies.retain(|s| s.expiry.map(|e| e > now).unwrap_or(true));
+
+        let mut matching: Vec<&Stored> = cookies.iter()
+            .filter(|s| if s.host_only { s.domain == host } else { domain_matches(&host, &s.domain) })
+            .filter(|s| path_matches(&url.path, &s.path))
+            .filter(|s| !s.cookie.secure || url.protocol == Protocol::Https)
+            .collect();
+
+        if matching.is_empty() {
+            return None;
+        }
+
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+
+        let pairs: Vec<String> = matching.iter()
+            .map(|s| format!("{}={}", s.cookie.name, s.cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Lock the cookies, even if a thread panicked while holding them
+    fn lock(&self) -> MutexGuard<'_, Vec<Stored>> {
+        match self.cookies.lock() {
+            Ok(c) => c,
+            Err(e) => e.into_inner()
+        }
+    }
+
+    fn next_created(&self) -> u64 {
+        let mut created = match self.created.lock() {
+            Ok(c) => c,
+            Err(e) => e.into_inner()
+        };
+        *created += 1;
+        *created
+    }
+}
+
+/// Split a 'name=value' pair, trimming whitespace around both. Pairs without
+/// '=' or a name are invalid
+fn split_pair(text: &str) -> Option<(&str, &str)> {
+    let mut pair = text.splitn(2, '=');
+    let name = pair.next().unwrap_or("").trim();
+    let value = pair.next()?.trim();
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name, value))
+}
+
+/// Characters allowed in a cookie value: visible ASCII except '"', ',', ';'
+/// and '\'
+#[cfg(test)]
+fn is_cookie_char(c: char) -> bool {
+    c.is_ascii_graphic() && !"\",;\\".contains(c)
+}
+
+/// Parse the date of 'Expires'
+fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    date::parse(text).or_else(|| {
+        NaiveDateTime::parse_from_str(text, NETSCAPE_DATE).ok()
+            .map(|date| DateTime::<Utc>::from_utc(date, Utc))
+    })
+}
+
+/// Check if a host is a domain or one of its subdomains. IP addresses only
+/// match themselves
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+
+    host.ends_with(domain)
+        && host[..host.len() - domain.len()].ends_with('.')
+        && host.parse::<IpAddr>().is_err()
+}
+
+/// Check if a request's path is a cookie's path or under it
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    if path == cookie_path {
+        return true;
+    }
+
+    path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/'))
+}
+
+/// The path of a cookie set without one: the 'directory' of the request's
+/// path, e.g. '/a/b' for '/a/b/c'
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(n) => path[..n].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::StatusCode;
+    use super::*;
+
+    #[test]
+    fn parse_attributes() {
+        let cookie = Cookie::parse("session=abc; Path=/buses; Domain=.Example.com; Max-Age=60; Secure; HttpOnly; SameSite=lax").unwrap();
+        assert_eq!(cookie.name, "session");
+        assert_eq!(cookie.value, "abc");
+        assert_eq!(cookie.path.as_deref(), Some("/buses"));
+        assert_eq!(cookie.domain.as_deref(), Some("example.com"));
+        assert_eq!(cookie.max_age, Some(60));
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.same_site, Some(SameSite::Lax));
+
+        // Invalid attributes are ignored
+        let cookie = Cookie::parse("a=1; Path=relative; Max-Age=1e3; SameSite=sometimes").unwrap();
+        assert_eq!((cookie.path, cookie.max_age, cookie.same_site), (None, None, None));
+
+        assert!(Cookie::parse("=1").is_err());
+        assert!(Cookie::parse("no-value").is_err());
+    }
+
+    #[test]
+    fn write_and_parse_back() {
+        let cookie = Cookie::new("session", "abc").path("/").max_age(3600).http_only().same_site(SameSite::Strict);
+        assert!(cookie.validate().is_ok());
+        assert_eq!(cookie.to_string(), "session=abc; Max-Age=3600; Path=/; HttpOnly; SameSite=Strict");
+        assert_eq!(Cookie::parse(&cookie.to_string()).unwrap(), cookie);
+    }
+
+    #[test]
+    fn validate_rejects_injection() {
+        assert!(Cookie::new("session", "\"quoted\"").validate().is_ok());
+        assert!(Cookie::new("session", "").validate().is_ok());
+
+        assert!(Cookie::new("session", "abc; Domain=evil.com").validate().is_err());
+        assert!(Cookie::new("session", "abc\r\nLocation: http://evil.com").validate().is_err());
+        assert!(Cookie::new("session", "a b").validate().is_err());
+        assert!(Cookie::new("session", "\"").validate().is_err());
+        assert!(Cookie::new("a=b", "c").validate().is_err());
+        assert!(Cookie::new("a;b", "c").validate().is_err());
+        assert!(Cookie::new("", "c").validate().is_err());
+        assert!(Cookie::new("session", "abc").path("/; Secure").validate().is_err());
+        assert!(Cookie::new("session", "abc").domain("example.com\n").validate().is_err());
+
+        let mut response = Response::new(StatusCode::OK, b"");
+        assert!(response.set_cookie(&Cookie::new("session", "abc\r\nX: 1")).is_err());
+        assert!(response.headers.get("Set-Cookie").is_none());
+        response.set_cookie(&Cookie::new("session", "abc")).unwrap();
+        assert_eq!(response.headers.get("Set-Cookie").as_deref(), Some("session=abc"));
+    }
+
+    #[test]
+    fn max_age_is_clamped() {
+        let now = Utc::now();
+        let cookie = Cookie::parse("a=1; Max-Age=99999999999999999999999").unwrap();
+        assert_eq!(cookie.expiry(now), Some(now + Duration::seconds(MAX_AGE_LIMIT)));
+
+        let cookie = Cookie::parse("a=1; Max-Age=-5").unwrap();
+        assert_eq!(cookie.expiry(now), Some(now - Duration::seconds(1)));
+    }
+
+    #[test]
+    fn jar_matches_domain_path_and_security() {
+        let jar = CookieJar::new();
+        let url = Url::parse("https://www.example.com/buses/800").unwrap();
+        jar.add(&url, Cookie::parse("host=1").unwrap());
+        jar.add(&url, Cookie::parse("domain=2; Domain=example.com; Path=/").unwrap());
+        jar.add(&url, Cookie::parse("secure=3; Secure; Path=/").unwrap());
+        // Another site's domain, or a top-level domain, can't be set
+        jar.add(&url, Cookie::parse("evil=4; Domain=evil.com").unwrap());
+        jar.add(&url, Cookie::parse("tld=5; Domain=com").unwrap());
+
+        let header = |url: &str| jar.header(&Url::parse(url).unwrap());
+        assert_eq!(header("https://www.example.com/buses/801").as_deref(), Some("host=1; domain=2; secure=3"));
+        assert_eq!(header("http://api.example.com/").as_deref(), Some("domain=2"));
+        assert_eq!(header("http://evil.com/"), None);
+
+        // An expired cookie deletes the stored one
+        jar.add(&url, Cookie::parse("domain=2; Domain=example.com; Path=/; Max-Age=0").unwrap());
+        assert_eq!(header("http://api.example.com/"), None);
+    }
+}
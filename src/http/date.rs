@@ -0,0 +1,30 @@
+use chrono::prelude::*;
+
+/// Format used by HTTP/1.1 to send dates, e.g. 'Sun, 06 Nov 1994 08:49:37 GMT'
+const IMF_FIXDATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Obsolete format from RFC 850, e.g. 'Sunday, 06-Nov-94 08:49:37 GMT'
+const RFC_850: &str = "%A, %d-%b-%y %H:%M:%S GMT";
+
+/// Obsolete format of C's asctime(), e.g. 'Sun Nov  6 08:49:37 1994'
+const ASCTIME: &str = "%a %b %e %H:%M:%S %Y";
+
+/// Parse an HTTP date. The three formats of RFC 9110 section 5.6.7 are
+/// accepted, as well as any date following RFC 2822
+pub fn parse(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+
+    if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    [IMF_FIXDATE, RFC_850, ASCTIME].iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|date| DateTime::<Utc>::from_utc(date, Utc))
+        .next()
+}
+
+/// Format a date the way HTTP/1.1 expects it
+pub fn format(date: &DateTime<Utc>) -> String {
+    date.format(IMF_FIXDATE).to_string()
+}
@@ -0,0 +1,597 @@
+use crate::http::{Error, Limit};
+
+/// Base length for length codes 257..285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+
+/// Extra bits for length codes 257..285
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distance for distance codes 0..29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Extra bits for distance codes 0..29
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// Order in which the lengths of the code length alphabet are sent
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Size of the sliding window, i.e. the longest distance of a match
+const WINDOW_SIZE: usize = 32768;
+
+/// Shortest and longest matches that can be encoded
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// Number of previous positions compared when looking for a match
+const MAX_CHAIN: usize = 128;
+
+/// Largest block that can be stored without compression
+const MAX_STORED: usize = 65535;
+
+/// Decode a body sent with the 'deflate' content coding, i.e. DEFLATE data
+/// in a zlib container (RFC 1950). Some servers send raw DEFLATE data
+/// instead, which is accepted as well. Fails if the decoded body is larger
+/// than the limit
+pub fn decode(data: &[u8], limit: u64) -> Result<Vec<u8>, Error> {
+    if !is_zlib_header(data) {
+        return inflate(data, limit).map(|(body, _)| body);
+    }
+
+    let (body, size) = inflate(&data[2..], limit)?;
+    let trailer = data.get(2 + size..2 + size + 4)
+        .ok_or(Error::body("Missing zlib checksum"))?;
+    let checksum = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+
+    if checksum != adler32(&body) {
+        return Err(Error::body("Invalid zlib checksum"));
+    }
+
+    Ok(body)
+}
+
+/// Encode a body with the 'deflate' content coding
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    // 32K window, default compression level, no dictionary
+    let mut encoded = vec![0x78, 0x9C];
+    encoded.extend_from_slice(&compress(data));
+    encoded.extend_from_slice(&adler32(data).to_be_bytes());
+    encoded
+}
+
+/// Check if data starts with a valid zlib header using DEFLATE
+fn is_zlib_header(data: &[u8]) -> bool {
+    if data.len() < 2 {
+        return false;
+    }
+
+    let (cmf, flg) = (data[0] as u16, data[1] as u16);
+    cmf & 0x0F == 8 && cmf >> 4 <= 7 && (cmf << 8 | flg) % 31 == 0 && flg & 0x20 == 0
+}
+
+/// Adler-32 checksum used by zlib
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // Sums can't overflow for 5552 bytes before being reduced
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    b << 16 | a
+}
+
+/// Decompress raw DEFLATE data (RFC 1951). Returns the data and the number
+/// of bytes of input used, so that a container can read what follows
+pub fn inflate(data: &[u8], limit: u64) -> Result<(Vec<u8>, usize), Error> {
+    let mut input = BitReader::new(data);
+    let mut output: Vec<u8> = Vec::new();
+
+    loop {
+        let last = input.bits(1)? == 1;
+
+        match input.bits(2)? {
+            0 => inflate_stored(&mut input, &mut output)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut input, &mut output, &literals, &distances, limit)?;
+            },
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut input)?;
+                inflate_block(&mut input, &mut output, &literals, &distances, limit)?;
+            },
+            _ => return Err(Error::body("Invalid DEFLATE block type"))
+        }
+
+        if output.len() as u64 > limit {
+            return Err(Error::TooLarge(Limit::Body));
+        }
+
+        if last {
+            return Ok((output, input.consumed()));
+        }
+    }
+}
+
+/// Copy a block stored without compression
+fn inflate_stored(input: &mut BitReader, output: &mut Vec<u8>) -> Result<(), Error> {
+    input.align();
+    let length = input.bytes(2)?;
+    let complement = input.bytes(2)?;
+
+    if length != !complement & 0xFFFF {
+        return Err(Error::body("Invalid DEFLATE stored block length"));
+    }
+
+    output.extend_from_slice(input.take(length as usize)?);
+    Ok(())
+}
+
+/// Decode a block compressed with Huffman codes
+fn inflate_block(input: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, limit: u64)
+    -> Result<(), Error>
+{
+    loop {
+        let symbol = literals.decode(input)? as usize;
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(Error::body("Invalid DEFLATE length code"));
+        }
+        let length = LENGTH_BASE[index] as usize + input.bits(LENGTH_EXTRA[index])? as usize;
+
+        let index = distances.decode(input)? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(Error::body("Invalid DEFLATE distance code"));
+        }
+        let distance = DISTANCE_BASE[index] as usize + input.bits(DISTANCE_EXTRA[index])? as usize;
+
+        if distance > output.len() {
+            return Err(Error::body("Invalid DEFLATE distance"));
+        }
+
+        if (output.len() + length) as u64 > limit {
+            return Err(Error::TooLarge(Limit::Body));
+        }
+
+        // The match can overlap the bytes it produces, so copy one at a time
+        let start = output.len() - distance;
+        for i in 0..length {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+}
+
+/// The codes used by blocks compressed with fixed Huffman codes
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8
+        };
+    }
+
+    // Building the fixed codes can't fail
+    let literals = Huffman::new(&lengths).unwrap();
+    let distances = Huffman::new(&[5u8; 30]).unwrap();
+    (literals, distances)
+}
+
+/// Read the codes sent at the start of a block compressed with dynamic
+/// Huffman codes
+fn dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_count = input.bits(4)? as usize + 4;
+
+    if literal_count > 286 || distance_count > 30 {
+        return Err(Error::body("Invalid DEFLATE code counts"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for position in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[*position] = input.bits(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths)?;
+
+    // Lengths of both alphabets are sent as a single sequence, where
+    // repeats can cross from one to the other
+    let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match codes.decode(input)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(Error::body("Invalid DEFLATE length repeat"))?;
+                (previous, 3 + input.bits(2)? as usize)
+            },
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize)
+        };
+
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err(Error::body("Invalid DEFLATE length repeat"));
+        }
+        lengths.extend(std::iter::repeat(length).take(repeat));
+    }
+
+    if lengths[256] == 0 {
+        return Err(Error::body("Missing DEFLATE end of block code"));
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+/// A canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; 16],
+    /// Symbols ordered by the length of their code, then by value
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+
+    /// Build the code from the length of the code of every symbol
+    fn new(lengths: &[u8]) -> Result<Huffman, Error> {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+
+        // Check that there aren't more codes of a length than possible
+        let mut left: i32 = 1;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(Error::body("Invalid DEFLATE Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Read the next symbol
+    fn decode(&self, input: &mut BitReader) -> Result<u16, Error> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for length in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::body("Invalid DEFLATE Huffman code"))
+    }
+}
+
+/// Reads DEFLATE data, where bits are packed starting with the least
+/// significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0, bit: 0 }
+    }
+
+    /// Read a number stored on a number of bits
+    fn bits(&mut self, count: u8) -> Result<u32, Error> {
+        let mut value = 0u32;
+
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or(Error::body("Truncated DEFLATE data"))?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Skip the bits left in the current byte
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    /// Read a little-endian number stored on a number of whole bytes
+    fn bytes(&mut self, count: usize) -> Result<u32, Error> {
+        let bytes = self.take(count)?;
+        Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32))
+    }
+
+    /// Take whole bytes. The reader must be aligned
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.data.get(self.pos..self.pos + count)
+            .ok_or(Error::body("Truncated DEFLATE data"))?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    /// Number of bytes read, including a partially read one
+    fn consumed(&self) -> usize {
+        if self.bit == 0 { self.pos } else { self.pos + 1 }
+    }
+}
+
+/// Compress data to raw DEFLATE data (RFC 1951). Repeated strings are
+/// replaced by references to a previous occurrence and sent with the fixed
+/// Huffman codes. Data that doesn't compress is stored as-is
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = BitWriter::new();
+    output.bits(1, 1);
+    output.bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (length, distance) = find_match(data, pos, &head, &previous);
+
+        if length >= MIN_MATCH {
+            write_length(&mut output, length);
+            write_distance(&mut output, distance);
+        } else {
+            write_literal(&mut output, data[pos] as u16);
+        }
+
+        // Every position is remembered so that later data can refer to it
+        let end = pos + length.max(1);
+        while pos < end {
+            if pos + MIN_MATCH <= data.len() {
+                let hash = hash(&data[pos..]);
+                previous[pos % WINDOW_SIZE] = head[hash];
+                head[hash] = pos;
+            }
+            pos += 1;
+        }
+    }
+
+    write_literal(&mut output, 256);
+    let compressed = output.finish();
+
+    if compressed.len() > stored_size(data) {
+        return store(data);
+    }
+
+    compressed
+}
+
+/// Size of data sent as blocks stored without compression, each block
+/// having a 5 bytes header
+fn stored_size(data: &[u8]) -> usize {
+    let blocks = ((data.len() + MAX_STORED - 1) / MAX_STORED).max(1);
+    data.len() + blocks * 5
+}
+
+/// Send data as blocks stored without compression
+fn store(data: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(stored_size(data));
+    let mut blocks = data.chunks(MAX_STORED).peekable();
+
+    if blocks.peek().is_none() {
+        return vec![0x01, 0x00, 0x00, 0xFF, 0xFF];
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        output.push(last as u8);
+        output.extend_from_slice(&length.to_le_bytes());
+        output.extend_from_slice(&(!length).to_le_bytes());
+        output.extend_from_slice(block);
+    }
+
+    output
+}
+
+/// Hash of the 3 bytes starting a match
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    (value.wrapping_mul(2654435761) >> 7) & ((1 << 15) - 1)
+}
+
+/// Find the longest previous occurrence of the data at a position. Returns
+/// its length and distance
+fn find_match(data: &[u8], pos: usize, head: &[usize], previous: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+
+    let max = (data.len() - pos).min(MAX_MATCH);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(&data[pos..])];
+
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+            break;
+        }
+
+        let length = data[candidate..candidate + max].iter()
+            .zip(&data[pos..pos + max])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == max {
+                break;
+            }
+        }
+
+        let next = previous[candidate % WINDOW_SIZE];
+        // The slot may have been reused by a more recent position
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+
+    best
+}
+
+/// Write a literal byte or the end of block code with the fixed Huffman codes
+fn write_literal(output: &mut BitWriter, symbol: u16) {
+    match symbol {
+        0..=143 => output.code(0x30 + symbol as u32, 8),
+        144..=255 => output.code(0x190 + (symbol - 144) as u32, 9),
+        256..=279 => output.code((symbol - 256) as u32, 7),
+        _ => output.code(0xC0 + (symbol - 280) as u32, 8)
+    }
+}
+
+/// Write the length of a match
+fn write_length(output: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap_or(0);
+    write_literal(output, 257 + index as u16);
+    output.bits((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index]);
+}
+
+/// Write the distance of a match
+fn write_distance(output: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap_or(0);
+    output.code(index as u32, 5);
+    output.bits((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index]);
+}
+
+/// Writes DEFLATE data, packing bits starting with the least significant
+/// bit of each byte
+struct BitWriter {
+    output: Vec<u8>,
+    value: u32,
+    count: u8,
+}
+
+impl BitWriter {
+
+    fn new() -> BitWriter {
+        BitWriter { output: Vec::new(), value: 0, count: 0 }
+    }
+
+    /// Write a number on a number of bits, least significant bit first
+    fn bits(&mut self, value: u32, count: u8) {
+        self.value |= value << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.output.push(self.value as u8);
+            self.value >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is sent most significant bit first
+    fn code(&mut self, code: u32, length: u8) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.bits(reversed, length);
+    }
+
+    /// Get the data, padding the last byte with zeros
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.value as u8);
+        }
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "1234 - 800: 4 minutes\n";
+
+    /// Output of Python's 'zlib.compress(text * 8, 9)'
+    const ZLIB: [u8; 33] = [
+        0x78, 0xda, 0x33, 0x34, 0x32, 0x36, 0x51, 0xd0, 0x55, 0xb0, 0x30, 0x30,
+        0xb0, 0x52, 0x30, 0x51, 0xc8, 0xcd, 0xcc, 0x2b, 0x2d, 0x49, 0x2d, 0xe6,
+        0x32, 0x1c, 0xb4, 0xa2, 0x00, 0x0a, 0xf1, 0x2c, 0x61,
+    ];
+
+    #[test]
+    fn decodes_zlib_and_raw_deflate() {
+        let expected = TEXT.repeat(8);
+        assert_eq!(decode(&ZLIB, 1 << 20).unwrap(), expected.as_bytes());
+        // Some servers send the deflate stream without the zlib wrapper
+        assert_eq!(decode(&ZLIB[2..ZLIB.len() - 4], 1 << 20).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let noise: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let repeated = TEXT.repeat(5000);
+        for input in [&b""[..], b"a", repeated.as_bytes(), &noise].iter() {
+            assert_eq!(&decode(&encode(input), 1 << 20).unwrap(), input);
+        }
+        assert!(encode(repeated.as_bytes()).len() < repeated.len() / 10);
+    }
+
+    #[test]
+    fn limit_stops_zip_bombs() {
+        let bomb = encode(&vec![b'a'; 1 << 20]);
+        assert!(matches!(decode(&bomb, 1000), Err(Error::TooLarge(Limit::Body))));
+    }
+}
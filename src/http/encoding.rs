@@ -0,0 +1,143 @@
+use crate::http::{Error, HeaderSet};
+use crate::http::{deflate, gzip};
+
+/// Content codings the client asks for in its 'Accept-Encoding' header when
+/// configured to accept compressed responses
+pub const ACCEPTED: &str = "gzip, deflate";
+
+/// Decode a body according to its 'Content-Encoding' header. Once decoded,
+/// the 'Content-Encoding' and 'Content-Length' headers are removed since they
+/// no longer describe the body. A body with a coding this module doesn't
+/// know is left as-is. Fails if the decoded body is larger than the limit
+pub fn decode(headers: &mut HeaderSet, body: Vec<u8>, limit: u64) -> Result<Vec<u8>, Error> {
+    let codings: Vec<String> = headers.get_all("Content-Encoding").iter()
+        .flat_map(|v| v.split(','))
+        .map(|c| c.trim().to_ascii_lowercase())
+        .filter(|c| !c.is_empty() && c != "identity")
+        .collect();
+
+    if codings.is_empty() || body.is_empty() || !codings.iter().all(|c| is_supported(c)) {
+        return Ok(body);
+    }
+
+    // Codings are listed in the order they were applied
+    let mut body = body;
+    for coding in codings.iter().rev() {
+        body = match coding.as_str() {
+            "gzip" | "x-gzip" => gzip::decode(&body, limit)?,
+            _ => deflate::decode(&body, limit)?
+        };
+    }
+
+    headers.remove("Content-Encoding");
+    headers.remove("Content-Length");
+    Ok(body)
+}
+
+/// Encode a body with a content coding. Returns None if the coding isn't
+/// supported
+pub fn encode(coding: &str, body: &[u8]) -> Option<Vec<u8>> {
+    match coding.to_ascii_lowercase().as_str() {
+        "gzip" | "x-gzip" => Some(gzip::encode(body)),
+        "deflate" => Some(deflate::encode(body)),
+        _ => None
+    }
+}
+
+/// Check if this module can decode and encode a content coding
+fn is_supported(coding: &str) -> bool {
+    matches!(coding, "gzip" | "x-gzip" | "deflate")
+}
+
+/// Choose the content coding to compress a response with from the request's
+/// 'Accept-Encoding' header, e.g. 'gzip;q=1.0, deflate;q=0.5'. Gzip is
+/// preferred when both are equally acceptable. Returns None if the client
+/// didn't ask for a supported coding
+pub fn preferred(headers: &HeaderSet) -> Option<&'static str> {
+    let mut accepted: Vec<(String, f32)> = Vec::new();
+
+    for value in headers.get_all("Accept-Encoding").iter() {
+        for item in value.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let quality = params
+                .filter_map(|p| {
+                    let mut p = p.splitn(2, '=');
+                    match (p.next()?.trim(), p.next()?.trim()) {
+                        (name, value) if name.eq_ignore_ascii_case("q") => value.parse::<f32>().ok(),
+                        _ => None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+
+            if !coding.is_empty() {
+                accepted.push((coding, quality));
+            }
+        }
+    }
+
+    // A coding that isn't listed gets the quality of '*' if there is one
+    let quality = |name: &str| -> f32 {
+        accepted.iter()
+            .find(|(c, _)| c == name)
+            .or_else(|| accepted.iter().find(|(c, _)| c == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+
+    let (gzip, deflate) = (quality("gzip"), quality("deflate"));
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some("gzip")
+    } else if deflate > 0.0 {
+        Some("deflate")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::Limit;
+    use super::*;
+
+    #[test]
+    fn decode_removes_headers() {
+        let mut headers = HeaderSet::new();
+        headers.add("Content-Encoding", "gzip");
+        let body = gzip::encode(b"hello");
+        headers.add("Content-Length", &body.len().to_string());
+
+        assert_eq!(decode(&mut headers, body, 1024).unwrap(), b"hello");
+        assert!(headers.get("Content-Encoding").is_none());
+        assert!(headers.get("Content-Length").is_none());
+    }
+
+    #[test]
+    fn unknown_coding_is_left_as_is() {
+        let mut headers = HeaderSet::new();
+        headers.add("Content-Encoding", "br");
+        assert_eq!(decode(&mut headers, b"abc".to_vec(), 1024).unwrap(), b"abc");
+        assert_eq!(headers.get("Content-Encoding").as_deref(), Some("br"));
+    }
+
+    #[test]
+    fn decode_enforces_the_limit() {
+        let mut headers = HeaderSet::new();
+        headers.add("Content-Encoding", "gzip");
+        let bomb = gzip::encode(&vec![0; 1 << 20]);
+        assert!(matches!(decode(&mut headers, bomb, 64 * 1024), Err(Error::TooLarge(Limit::Body))));
+    }
+
+    #[test]
+    fn preferred_coding() {
+        let mut headers = HeaderSet::new();
+        headers.add("Accept-Encoding", "deflate;q=0.5, gzip;q=1.0");
+        assert_eq!(preferred(&headers), Some("gzip"));
+
+        let mut headers = HeaderSet::new();
+        headers.add("Accept-Encoding", "gzip;q=0, br");
+        assert_eq!(preferred(&headers), None);
+    }
+}
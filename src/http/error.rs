@@ -4,19 +4,30 @@ use std::fmt;
 use std::io;
 use std::net::TcpStream;
 
+use crate::http::{Limit, StatusCode};
+
 /// Error struct for managing all errors in the HTTP module
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
+    /// The server couldn't be reached, nothing was sent to it
+    Connect(io::Error),
+    Timeout,
     Tls(native_tls::Error),
     Handshake(native_tls::HandshakeError<TcpStream>),
+    /// The first line of a request isn't a method, target and version
+    RequestLine,
     Verb,
     Query,
     Version,
     Status,
     Protocol,
     Header(String),
-    URL(String),
+    Body(String),
+    Url(String),
+    TooManyRedirects,
+    TooLarge(Limit),
+    Proxy(StatusCode),
 }
 
 impl Error {
@@ -25,8 +36,12 @@ impl Error {
         Error::Header(message.to_string())
     }
 
+    pub fn body(message: &str) -> Error {
+        Error::Body(message.to_string())
+    }
+
     pub fn url(message: &str) -> Error {
-        Error::URL(message.to_string())
+        Error::Url(message.to_string())
     }
 }
 
@@ -34,15 +49,22 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let message = match self {
             Error::Io(e) => format!("IO Error: {}", e),
+            Error::Connect(e) => format!("Connection Error: {}", e),
+            Error::Timeout => "HTTP error: timed out".to_string(),
             Error::Tls(e) => format!("TLS Error: {}", e),
             Error::Handshake(e) => format!("TLS Handshake Error: {}", e),
-            Error::Verb => format!("HTTP error: invalid request method"),
-            Error::Query => format!("HTTP error: invalid request URI"),
-            Error::Version => format!("HTTP error: only HTTP/1.1 is supported"),
-            Error::Status => format!("HTTP error: invalid status code"),
-            Error::Protocol => format!("HTTP error: unsupported protocol"),
+            Error::RequestLine => "HTTP error: invalid request line".to_string(),
+            Error::Verb => "HTTP error: invalid request method".to_string(),
+            Error::Query => "HTTP error: invalid request URI".to_string(),
+            Error::Version => "HTTP error: only HTTP/1.0 and HTTP/1.1 are supported".to_string(),
+            Error::Status => "HTTP error: invalid status code".to_string(),
+            Error::Protocol => "HTTP error: unsupported protocol".to_string(),
             Error::Header(e) => format!("HTTP error: invalid header: {}", e),
-            Error::URL(e) => format!("HTTP error: invalid URL: {}", e)
+            Error::Body(e) => format!("HTTP error: invalid body: {}", e),
+            Error::Url(e) => format!("HTTP error: invalid URL: {}", e),
+            Error::TooManyRedirects => "HTTP error: too many redirects".to_string(),
+            Error::TooLarge(l) => format!("HTTP error: {}", l),
+            Error::Proxy(c) => format!("HTTP error: proxy refused the connection: {} {}", c, c.reason().unwrap_or("")),
         };
         write!(f, "{}", message)
     }
@@ -50,7 +72,12 @@ impl fmt::Display for Error {
 
 impl From<io::Error> for Error {
    fn from(e: io::Error) -> Self {
-       Error::Io(e)
+       // Sockets are never set as non-blocking, so a read or write that
+       // would block means its timeout expired
+       match e.kind() {
+           io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+           _ => Error::Io(e)
+       }
    }
 }
 
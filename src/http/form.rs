@@ -0,0 +1,36 @@
+use crate::http::percent;
+
+/// Parse fields encoded with application/x-www-form-urlencoded, e.g.
+/// 'a=1&b=2', to their decoded names and values. Query strings use the same
+/// encoding as form bodies. Fields without a name are skipped
+pub fn parse_form(text: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+
+    for field in text.split('&') {
+        let mut field = field.splitn(2, '=');
+        let name = percent::decode_form(field.next().unwrap_or(""));
+        let value = percent::decode_form(field.next().unwrap_or(""));
+        if !name.is_empty() {
+            fields.push((name, value))
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let fields = parse_form("to=%2B14185551234&message=Bus+800+in+4%20min&=skipped&empty&a=b=c");
+        assert_eq!(fields, vec![
+            ("to".to_string(), "+14185551234".to_string()),
+            ("message".to_string(), "Bus 800 in 4 min".to_string()),
+            ("empty".to_string(), "".to_string()),
+            ("a".to_string(), "b=c".to_string()),
+        ]);
+        assert!(parse_form("").is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::http::Error;
+use crate::http::deflate;
+
+/// Flags of the gzip header
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Decode a body sent with the 'gzip' content coding (RFC 1952). Bodies made
+/// of multiple gzip members are decoded as their concatenation. Fails if the
+/// decoded body is larger than the limit
+pub fn decode(data: &[u8], limit: u64) -> Result<Vec<u8>, Error> {
+    let mut body: Vec<u8> = Vec::new();
+    let mut input = data;
+
+    loop {
+        let start = header_size(input)?;
+        let remaining = limit.saturating_sub(body.len() as u64);
+        let (member, size) = deflate::inflate(&input[start..], remaining)?;
+
+        let trailer = input.get(start + size..start + size + 8)
+            .ok_or(Error::body("Missing gzip trailer"))?;
+        let checksum = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let length = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+        if checksum != crc32(&member) || length != member.len() as u32 {
+            return Err(Error::body("Invalid gzip checksum"));
+        }
+
+        body.extend_from_slice(&member);
+        input = &input[start + size + 8..];
+
+        // Some encoders pad the data with zeros after the last member
+        if input.iter().all(|b| *b == 0) {
+            return Ok(body);
+        }
+    }
+}
+
+/// Encode a body with the 'gzip' content coding
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    // No flags, no modification time, unknown operating system
+    let mut encoded = vec![0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF];
+    encoded.extend_from_slice(&deflate::compress(data));
+    encoded.extend_from_slice(&crc32(data).to_le_bytes());
+    encoded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    encoded
+}
+
+/// Check the header of a gzip member and return its size
+fn header_size(data: &[u8]) -> Result<usize, Error> {
+    let truncated = || Error::body("Truncated gzip header");
+
+    let header = data.get(..10).ok_or_else(truncated)?;
+    if header[0] != 0x1F || header[1] != 0x8B || header[2] != 0x08 {
+        return Err(Error::body("Invalid gzip header"));
+    }
+
+    let flags = header[3];
+    let mut size = 10;
+
+    if flags & FEXTRA != 0 {
+        let length = data.get(size..size + 2).ok_or_else(truncated)?;
+        size += 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+    }
+
+    // The file name and comment end with a zero byte
+    for flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            let end = data.get(size..).ok_or_else(truncated)?
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or_else(truncated)?;
+            size += end + 1;
+        }
+    }
+
+    if flags & FHCRC != 0 {
+        size += 2;
+    }
+
+    if size > data.len() {
+        return Err(truncated());
+    }
+
+    Ok(size)
+}
+
+/// Lookup table of the CRC-32 checksum, built once at compile time
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 { 0xEDB88320 ^ (value >> 1) } else { value >> 1 };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 checksum used by gzip
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::Limit;
+    use super::*;
+
+    const TEXT: &str = "1234 - 800: 4 minutes\n";
+
+    /// Output of 'gzip -c -9 next_bus.txt', with the file name in the header
+    const GZIP_CLI: [u8; 58] = [
+        0x1f, 0x8b, 0x08, 0x08, 0x32, 0xbd, 0x1d, 0x5e, 0x02, 0x03, 0x6e, 0x65,
+        0x78, 0x74, 0x5f, 0x62, 0x75, 0x73, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x33,
+        0x34, 0x32, 0x36, 0x51, 0xd0, 0x55, 0xb0, 0x30, 0x30, 0xb0, 0x52, 0x30,
+        0x51, 0xc8, 0xcd, 0xcc, 0x2b, 0x2d, 0x49, 0x2d, 0xe6, 0x32, 0x1c, 0xb4,
+        0xa2, 0x00, 0xd0, 0x16, 0x0f, 0x4e, 0xb0, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decodes_gzip_cli_output() {
+        assert_eq!(decode(&GZIP_CLI, 1 << 20).unwrap(), TEXT.repeat(8).as_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(i) % 251) as u8).collect();
+        for input in [&b""[..], TEXT.as_bytes(), &data].iter() {
+            assert_eq!(&decode(&encode(input), 1 << 20).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let mut data = encode(b"first ");
+        data.extend_from_slice(&encode(b"second"));
+        assert_eq!(decode(&data, 1 << 20).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut data = GZIP_CLI;
+        data[50] ^= 1;
+        assert!(decode(&data, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn limit_stops_zip_bombs() {
+        // A megabyte of zeros compresses to a few kilobytes
+        let bomb = encode(&vec![0; 1 << 20]);
+        assert!(bomb.len() < 16 * 1024);
+        assert!(matches!(decode(&bomb, 64 * 1024), Err(Error::TooLarge(Limit::Body))));
+        assert_eq!(decode(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
@@ -1,8 +1,9 @@
-use std::io::{Read, Write};
-use std::io::BufReader;
+use std::io::Write;
 use std::io::BufRead;
 
-use crate::http::Error;
+use crate::http::{Error, Limit, Limits, MediaType};
+use crate::http::io;
+use crate::http::media::is_token;
 
 /// A Header in an HTTP request or response
 #[derive(Debug)]
@@ -11,7 +12,8 @@ pub struct Header {
     pub value: String
 }
 
-/// A set of Headers in an HTTP request or response
+/// A set of Headers in an HTTP request or response. Header names are
+/// case-insensitive, and a name can have more than one value
 #[derive(Debug)]
 pub struct HeaderSet {
     headers: Vec<Header>
@@ -23,10 +25,20 @@ impl HeaderSet {
         HeaderSet{ headers: Vec::new() }
     }
 
+    /// Check if the set has no headers
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Iterate over all headers in the order they were added
+    pub fn iter(&self) -> std::slice::Iter<'_, Header> {
+        self.headers.iter()
+    }
+
     /// Add a header to the set.
     ///
-    /// Duplicate header keys are not managed. If Headers with the same name
-    /// are added, they will be sent in the order they were added
+    /// Headers with the same name are kept and sent in the order they were
+    /// added. Use `set` to replace a header instead
     pub fn add(&mut self, name: &str, value: &str) {
         self.headers.push(Header{
             key: name.to_string(),
@@ -36,50 +48,204 @@ impl HeaderSet {
 
     /// Add a header to the set if it doesn't already exist
     pub fn add_default(&mut self, name: &str, value: &str) { 
-        if let None = self.get(name) {
+        if self.get(name).is_none() {
             self.add(name, value);
         }
     }
 
+    /// Replace all headers with the corresponding name by a single header
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.add(name, value);
+    }
+
+    /// Remove all headers with the corresponding name from the set
+    pub fn remove(&mut self, name: &str) {
+        self.headers.retain(|h| !h.key.eq_ignore_ascii_case(name));
+    }
+
     /// Get the value of the first header with the corresponding name from the set
     pub fn get(&self, name: &str) -> Option<String> {
         self.headers.iter()
-            .filter(|h| h.key == name)
+            .filter(|h| h.key.eq_ignore_ascii_case(name))
             .map(|h| h.value.to_string())
             .next()
     }
 
-    /// Read all headers from a TCP socket and convert them to a HeaderSet
-    pub fn read<T: Read>(reader: &mut BufReader<&mut T>) -> Result<HeaderSet, Error> {
+    /// Get the values of all headers with the corresponding name, in the
+    /// order they were added
+    pub fn get_all(&self, name: &str) -> Vec<String> {
+        self.headers.iter()
+            .filter(|h| h.key.eq_ignore_ascii_case(name))
+            .map(|h| h.value.to_string())
+            .collect()
+    }
+
+    /// Check if a header holding a comma separated list, e.g. 'Connection',
+    /// contains a token. Tokens are compared case-insensitively
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers.iter()
+            .filter(|h| h.key.eq_ignore_ascii_case(name))
+            .flat_map(|h| h.value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// The length of the body in the 'Content-Length' header. Repeated values
+    /// are accepted if they are all the same, as allowed by RFC 9110 section 8.6.
+    /// Returns None if the header is missing or invalid
+    pub fn content_length(&self) -> Option<u64> {
+        let mut length = None;
+
+        for value in self.get_all("Content-Length").iter().flat_map(|v| v.split(',')) {
+            let value = value.trim();
+            if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let value = value.parse::<u64>().ok()?;
+            if length.is_some() && length != Some(value) {
+                return None;
+            }
+            length = Some(value);
+        }
+
+        length
+    }
+
+    /// The media type of the body in the 'Content-Type' header. Returns None
+    /// if the header is missing or invalid
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(&self.get("Content-Type")?).ok()
+    }
+
+    /// Read all headers from a TCP socket and convert them to a HeaderSet.
+    /// Fails if there are too many headers or if a line is too long
+    pub fn read<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<HeaderSet, Error> {
         let mut headers: Vec<Header> = Vec::new();
 
-        for line in reader.lines() {
-            let line = line?;
-            if line == "" {
+        loop {
+            // An empty line ends the headers, as does the end of the stream
+            let line = io::read_line(reader, limits.max_header_line, Limit::HeaderLine)?;
+            if line.is_empty() {
                 return Ok(HeaderSet{headers})
             }
 
-            let mut parts = line.splitn(2, ": ");
-            let key = parts.next().ok_or(Error::header("Missing header name"))?;
+            // Obsolete line folding continues the value of the previous header
+            if line.starts_with(' ') || line.starts_with('\t') {
+                let header = headers.last_mut().ok_or(Error::header("Invalid header folding"))?;
+                if header.key.len() + header.value.len() + line.len() > limits.max_header_line {
+                    return Err(Error::TooLarge(Limit::HeaderLine));
+                }
+                header.value.push(' ');
+                header.value.push_str(line.trim());
+                continue;
+            }
+
+            if headers.len() >= limits.max_headers {
+                return Err(Error::TooLarge(Limit::Headers));
+            }
+
+            // Whitespace around the colon and the value is optional
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap_or("").trim();
             let value = parts.next().ok_or(Error::header("Missing header value"))?;
+
+            if !is_token(key) {
+                return Err(Error::header("Invalid header name"));
+            }
+
             headers.push(
                 Header{ 
                     key: key.to_string(),
-                    value: value.to_string()
+                    value: value.trim().to_string()
                 }
             );
         }
-
-        return Ok(HeaderSet{headers})
     }
 
     /// Write all headers in the set to a TCP socket
     pub fn write<T: Write>(&self, stream: &mut T) -> Result<(), Error> {
         for header in self.headers.iter() {
             let line = format!("{}: {}\r\n", header.key, header.value);
-            stream.write(line.as_bytes())?;
+            stream.write_all(line.as_bytes())?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<HeaderSet, Error> {
+        HeaderSet::read(&mut text.as_bytes(), &Limits::default())
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let headers = read("Content-Type: text/plain\r\nX-Stop: 1234\r\nx-stop: 5678\r\n\r\n").unwrap();
+        assert_eq!(headers.get("content-type").as_deref(), Some("text/plain"));
+        assert_eq!(headers.get_all("X-STOP"), vec!["1234", "5678"]);
+    }
+
+    #[test]
+    fn trims_optional_whitespace() {
+        let headers = read("Host:localhost\r\nAccept: \t text/html \t\r\nEmpty:\r\n\r\n").unwrap();
+        assert_eq!(headers.get("Host").as_deref(), Some("localhost"));
+        assert_eq!(headers.get("Accept").as_deref(), Some("text/html"));
+        assert_eq!(headers.get("Empty").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn unfolds_obsolete_line_folding() {
+        let headers = read("X-Message: first\r\n  second\r\n\tthird\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(headers.get("X-Message").as_deref(), Some("first second third"));
+        assert_eq!(headers.get("Host").as_deref(), Some("localhost"));
+
+        assert!(read(" folded: before any header\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert!(read("Bad Name: value\r\n\r\n").is_err());
+        assert!(read(": value\r\n\r\n").is_err());
+        assert!(read("No colon\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits { max_headers: 2, max_header_line: 32, ..Limits::default() };
+
+        let text = "A: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(matches!(HeaderSet::read(&mut text.as_bytes(), &limits), Err(Error::TooLarge(Limit::Headers))));
+
+        let text = format!("A: {}\r\n\r\n", "x".repeat(40));
+        assert!(matches!(HeaderSet::read(&mut text.as_bytes(), &limits), Err(Error::TooLarge(Limit::HeaderLine))));
+
+        // Folding can't get around the line limit
+        let text = format!("A: {}\r\n {}\r\n\r\n", "x".repeat(20), "x".repeat(20));
+        assert!(matches!(HeaderSet::read(&mut text.as_bytes(), &limits), Err(Error::TooLarge(Limit::HeaderLine))));
+    }
+
+    #[test]
+    fn repeated_content_length() {
+        let headers = read("Content-Length: 42\r\nContent-Length: 42\r\n\r\n").unwrap();
+        assert_eq!(headers.content_length(), Some(42));
+
+        let headers = read("Content-Length: 42, 42\r\n\r\n").unwrap();
+        assert_eq!(headers.content_length(), Some(42));
+
+        let headers = read("Content-Length: 42\r\nContent-Length: 43\r\n\r\n").unwrap();
+        assert_eq!(headers.content_length(), None);
+
+        let headers = read("Content-Length: +42\r\n\r\n").unwrap();
+        assert_eq!(headers.content_length(), None);
+    }
+
+    #[test]
+    fn tokens() {
+        let headers = read("Connection: Upgrade, Keep-Alive\r\n\r\n").unwrap();
+        assert!(headers.has_token("connection", "keep-alive"));
+        assert!(!headers.has_token("Connection", "close"));
+    }
+}
@@ -1,11 +1,187 @@
 extern crate native_tls;
 
-use std::io::{Read, Write};
+use std::io;
+use std::io::{BufRead, Read, Write};
 use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::http::{Error, Limit};
 
 /// This trait exists so that the HTTP lib can use encrypted or unencrypted
-/// TCP sockets interchangeably
-pub trait ReadWrite: Read + Write {}
+/// TCP sockets interchangeably. Sockets must be `Send` so that idle connections
+/// can be shared between threads
+pub trait ReadWrite: Read + Write + Send {
+
+    /// Set how long a read can block before failing. `None` blocks forever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Set how long a write can block before failing. `None` blocks forever
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadWrite for TcpStream {
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl ReadWrite for native_tls::TlsStream<TcpStream> {
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+}
+
+/// Shorten a timeout so that it ends at the deadline. Fails if the deadline
+/// has already passed
+pub fn until(timeout: Option<Duration>, deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    let remaining = match deadline {
+        Some(d) => {
+            let now = Instant::now();
+            if now >= d {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"));
+            }
+            Some(d - now)
+        },
+        None => None
+    };
+
+    match (timeout, remaining) {
+        (Some(t), Some(r)) => Ok(Some(t.min(r))),
+        (t, r) => Ok(t.or(r))
+    }
+}
+
+/// A socket where every read and write has a timeout, and where all of them
+/// fail once a deadline has passed. The deadline protects against a peer that
+/// keeps a connection busy by sending data very slowly. When there is no end
+/// in sight, e.g. for a large upload, a minimum rate can be required instead.
+pub struct TimedStream {
+    stream: Box<dyn ReadWrite>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    /// Number of bytes that must be read in each interval
+    min_read_rate: Option<(usize, Duration)>,
+    /// Start of the current interval, and the bytes read since
+    window: (Instant, usize),
+    /// A read failed because the peer was too slow
+    timed_out: bool,
+}
+
+impl TimedStream {
+
+    pub fn new(stream: Box<dyn ReadWrite>, read_timeout: Option<Duration>, write_timeout: Option<Duration>) -> TimedStream {
+        TimedStream {
+            stream,
+            read_timeout,
+            write_timeout,
+            deadline: None,
+            min_read_rate: None,
+            window: (Instant::now(), 0),
+            timed_out: false,
+        }
+    }
+
+    /// Set the moment after which reads and writes fail. `None` removes the deadline
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Make reads fail unless at least `bytes` are read in every `interval`,
+    /// starting now. `None` removes the minimum rate
+    pub fn set_min_read_rate(&mut self, rate: Option<(usize, Duration)>) {
+        self.min_read_rate = rate;
+        self.window = (Instant::now(), 0);
+    }
+
+    /// Check if a read failed because the peer didn't send data in time
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Forget that a read timed out, e.g. before the next request
+    pub fn reset_timed_out(&mut self) {
+        self.timed_out = false;
+    }
+
+    /// Get the socket back, without any timeout
+    pub fn into_inner(self) -> Box<dyn ReadWrite> {
+        self.stream
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = match self.min_read_rate {
+            Some((_, interval)) => {
+                let end = self.window.0 + interval;
+                Some(self.deadline.map_or(end, |d| d.min(end)))
+            },
+            None => self.deadline
+        };
+
+        let result = until(self.read_timeout, deadline)
+            .and_then(|timeout| self.stream.set_read_timeout(timeout))
+            .and_then(|_| self.stream.read(buf));
+
+        match &result {
+            Ok(size) => {
+                self.window.1 += size;
+                if let Some((bytes, _)) = self.min_read_rate {
+                    if self.window.1 >= bytes {
+                        self.window = (Instant::now(), 0);
+                    }
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {
+                self.timed_out = true;
+            },
+            Err(_) => {}
+        }
+
+        result
+    }
+}
+
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let timeout = until(self.write_timeout, self.deadline)?;
+        self.stream.set_write_timeout(timeout)?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Read a line ending with CRLF or LF, without its line ending. Fails with
+/// the limit if the line is longer than `max` bytes. Returns an empty
+/// string at the end of the stream
+pub fn read_line<R: BufRead>(reader: &mut R, max: usize, limit: Limit) -> Result<String, Error> {
+    let mut line = String::new();
+    reader.by_ref().take(max as u64 + 2).read_line(&mut line)?;
+
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+
+    if line.len() > max {
+        return Err(Error::TooLarge(limit));
+    }
 
-impl ReadWrite for TcpStream {}
-impl ReadWrite for native_tls::TlsStream<TcpStream> {}
+    Ok(line)
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// Largest parts of a request or response that are accepted from a peer,
+/// so that it can't make us allocate an unbounded amount of memory
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Number of headers, or of trailers after a chunked body
+    pub max_headers: usize,
+    /// Longest header line, or status line of a response
+    pub max_header_line: usize,
+    /// Longest request line, i.e. the verb, URL and version of a request
+    pub max_request_line: usize,
+    /// Largest body, once decompressed
+    pub max_body_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_headers: 100,
+            max_header_line: 8 * 1024,
+            max_request_line: 8 * 1024,
+            max_body_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The limit that was exceeded when reading a request or response
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Limit {
+    Headers,
+    HeaderLine,
+    RequestLine,
+    Body,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Limit::Headers => "too many headers",
+            Limit::HeaderLine => "header line too long",
+            Limit::RequestLine => "request line too long",
+            Limit::Body => "body too large",
+        };
+        write!(f, "{}", message)
+    }
+}
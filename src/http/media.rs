@@ -0,0 +1,228 @@
+use std::fmt;
+
+use crate::http::Error;
+
+/// A media type, as sent in a 'Content-Type' header, e.g.
+/// 'text/html; charset=utf-8'. Following RFC 9110 section 8.3.1, the type,
+/// subtype and parameter names are case-insensitive and are kept in lowercase.
+/// Parameter values keep their case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+    pub kind: String,
+    pub subtype: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+
+    pub fn new(kind: &str, subtype: &str) -> MediaType {
+        MediaType {
+            kind: kind.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Parse a media type with its parameters
+    pub fn parse(text: &str) -> Result<MediaType, Error> {
+        let (essence, params) = parse_params(text)?;
+        let mut essence = essence.splitn(2, '/');
+        let kind = essence.next().unwrap_or("").trim();
+        let subtype = essence.next().unwrap_or("").trim();
+
+        if !is_token(kind) || !is_token(subtype) {
+            return Err(Error::header("Invalid media type"));
+        }
+
+        let mut media = MediaType::new(kind, subtype);
+        media.params = params;
+        Ok(media)
+    }
+
+    /// The type and subtype, without the parameters, e.g. 'text/html'
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.kind, self.subtype)
+    }
+
+    /// Get the value of a parameter. Names are case-insensitive
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Add a parameter, e.g. 'charset'
+    #[cfg(test)]
+    pub fn add_param(&mut self, name: &str, value: &str) {
+        self.params.push((name.to_ascii_lowercase(), value.to_string()));
+    }
+
+    /// The character set of a text media type
+    #[cfg(test)]
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.subtype)?;
+
+        for (name, value) in self.params.iter() {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "; {}=\"{}\"", name, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parameters of a header value, as names and values
+pub type Parameters = Vec<(String, String)>;
+
+/// Split a header value followed by parameters, e.g.
+/// 'form-data; name="file"', into the value and the parameters. Parameter
+/// names are kept in lowercase
+pub fn parse_params(text: &str) -> Result<(&str, Parameters), Error> {
+    let mut parts = Scanner::new(text);
+    let value = parts.until(&[';']).trim();
+    let mut params: Parameters = Vec::new();
+
+    while parts.next(';') {
+        let name = parts.until(&[';', '=']).trim().to_ascii_lowercase();
+        // Parameters without a value, e.g. after a trailing ';', are ignored
+        if !parts.next('=') {
+            continue;
+        }
+        let value = parts.value()?;
+        if is_token(&name) {
+            params.push((name, value));
+        }
+    }
+
+    Ok((value, params))
+}
+
+/// Check if a string is a token, as defined by RFC 9110 section 5.6.2
+pub fn is_token(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| {
+        c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+    })
+}
+
+/// Splits a header value on delimiters, skipping over quoted strings
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+
+    fn new(text: &'a str) -> Scanner<'a> {
+        Scanner { text, pos: 0 }
+    }
+
+    /// Take everything up to one of the delimiters, or the rest of the text
+    fn until(&mut self, delimiters: &[char]) -> &'a str {
+        let rest = &self.text[self.pos..];
+        let end = rest.find(delimiters).unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+
+    /// Skip over the delimiter if it's the next character
+    fn next(&mut self, delimiter: char) -> bool {
+        if self.text[self.pos..].starts_with(delimiter) {
+            self.pos += delimiter.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take a parameter value, either a token or a quoted string
+    fn value(&mut self) -> Result<String, Error> {
+        let rest = &self.text[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+
+        if !trimmed.starts_with('"') {
+            let value = self.until(&[';']).trim();
+            return Ok(value.to_string());
+        }
+
+        let mut value = String::new();
+        let mut chars = trimmed.char_indices().skip(1);
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    // Anything between the closing quote and the next
+                    // parameter must be whitespace
+                    if !self.until(&[';']).trim().is_empty() {
+                        return Err(Error::header("Invalid quoted string"));
+                    }
+                    return Ok(value);
+                },
+                '\\' => match chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => break
+                },
+                c => value.push(c)
+            }
+        }
+
+        Err(Error::header("Unterminated quoted string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_is_case_insensitive() {
+        let media = MediaType::parse("Text/HTML; Charset=UTF-8").unwrap();
+        assert_eq!(media.essence(), "text/html");
+        assert_eq!(media.charset(), Some("UTF-8"));
+        assert_eq!(media.param("CHARSET"), Some("UTF-8"));
+    }
+
+    #[test]
+    fn trims_whitespace() {
+        let media = MediaType::parse(" application/json ;  charset = utf-8 ; ").unwrap();
+        assert_eq!(media.essence(), "application/json");
+        assert_eq!(media.params, vec![("charset".to_string(), "utf-8".to_string())]);
+    }
+
+    #[test]
+    fn quoted_parameters() {
+        let media = MediaType::parse(r#"multipart/form-data; boundary="a; b=\"c\""; charset=utf-8"#).unwrap();
+        assert_eq!(media.param("boundary"), Some(r#"a; b="c""#));
+        assert_eq!(media.charset(), Some("utf-8"));
+
+        assert!(MediaType::parse(r#"text/plain; charset="utf-8"#).is_err());
+        assert!(MediaType::parse(r#"text/plain; charset="utf-8" x"#).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_types() {
+        assert!(MediaType::parse("text").is_err());
+        assert!(MediaType::parse("text/").is_err());
+        assert!(MediaType::parse("text html/plain").is_err());
+    }
+
+    #[test]
+    fn display_quotes_when_needed() {
+        let mut media = MediaType::new("Multipart", "Form-Data");
+        media.add_param("Boundary", "a b\"c");
+        media.add_param("charset", "utf-8");
+        assert_eq!(media.to_string(), r#"multipart/form-data; boundary="a b\"c"; charset=utf-8"#);
+        assert_eq!(MediaType::parse(&media.to_string()).unwrap(), media);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+use std::sync::Mutex;
+
+use crate::http::{Error, Request, Response, StatusCode, Transport, Verb};
+
+/// Function building the response to a request that matched, or the error
+/// sending it fails with
+pub type Responder = dyn Fn(&Request) -> Result<Response, Error> + Send + Sync;
+
+/// Requests that a canned response is given for. The request's host and path
+/// must be the same, and every argument listed must be present with the same
+/// value. Other arguments are ignored
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub verb: Verb,
+    pub host: String,
+    pub path: String,
+    pub args: Vec<(String, String)>,
+}
+
+impl Matcher {
+
+    pub fn new(verb: Verb, host: &str, path: &str) -> Matcher {
+        Matcher {
+            verb,
+            host: host.to_ascii_lowercase(),
+            path: path.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Also require a query argument, e.g. 'method=sendSMS'
+    pub fn arg(mut self, name: &str, value: &str) -> Matcher {
+        self.args.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Check if a request is one this matcher is for
+    pub fn matches(&self, request: &Request) -> bool {
+        self.verb == request.verb
+            && self.host == request.url.host
+            && self.path == request.url.path
+            && self.args.iter().all(|(name, value)| request.url.get_arg(name).as_ref() == Some(value))
+    }
+}
+
+/// A transport that never touches the network. Requests get the response of
+/// the first matcher they match, and are recorded so that tests can check
+/// what was sent. Requests that don't match anything fail like a connection
+/// that was refused
+pub struct MockTransport {
+    routes: Mutex<Vec<(Matcher, Box<Responder>)>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+impl MockTransport {
+
+    pub fn new() -> MockTransport {
+        MockTransport {
+            routes: Mutex::new(Vec::new()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answer the requests a matcher is for with a status code and body
+    pub fn respond(&self, matcher: Matcher, code: StatusCode, body: &[u8]) {
+        let body = body.to_vec();
+        self.respond_with(matcher, move |_| Response::new(code, &body));
+    }
+
+    /// Answer the requests a matcher is for with the response built by a function
+    pub fn respond_with<F>(&self, matcher: Matcher, responder: F)
+        where F: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.add(matcher, Box::new(move |request| Ok(responder(request))));
+    }
+
+    /// Make the requests a matcher is for fail, e.g. with `Error::Timeout`
+    pub fn fail_with<F>(&self, matcher: Matcher, error: F)
+        where F: Fn() -> Error + Send + Sync + 'static
+    {
+        self.add(matcher, Box::new(move |_| Err(error())));
+    }
+
+    /// Take the requests sent so far, oldest first
+    pub fn take_requests(&self) -> Vec<Request> {
+        match self.requests.lock() {
+            Ok(mut requests) => requests.drain(..).collect(),
+            Err(_) => Vec::new()
+        }
+    }
+
+    fn add(&self, matcher: Matcher, responder: Box<Responder>) {
+        if let Ok(mut routes) = self.routes.lock() {
+            routes.push((matcher, responder));
+        }
+    }
+}
+
+impl Transport for MockTransport {
+
+    fn send(&self, mut request: Request) -> Result<Response, Error> {
+        // Keep a streamed body so that it can be checked later
+        request.body.buffer()?;
+
+        let response = match self.routes.lock() {
+            Ok(routes) => routes.iter()
+                .find(|(matcher, _)| matcher.matches(&request))
+                .map(|(_, responder)| responder(&request)),
+            Err(_) => None
+        };
+
+        let message = format!("no mock response for {} {}", request.verb.format(), request.url);
+
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.push(request);
+        }
+
+        match response {
+            Some(r) => r,
+            None => Err(Error::Connect(io::Error::new(io::ErrorKind::ConnectionRefused, message)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::Url;
+    use super::*;
+
+    #[test]
+    fn unmatched_request_is_refused() {
+        let mock = MockTransport::new();
+        mock.respond(Matcher::new(Verb::Get, "example.com", "/"), StatusCode::OK, b"OK");
+
+        let request = Request::new(Verb::Get, Url::parse("http://example.com/other").unwrap());
+        assert!(matches!(mock.send(request), Err(Error::Connect(e)) if e.kind() == io::ErrorKind::ConnectionRefused));
+        assert_eq!(mock.take_requests().len(), 1);
+    }
+}
@@ -1,19 +1,57 @@
+mod base64;
 mod body;
+mod cookie;
+mod deflate;
+mod encoding;
 mod error;
+mod form;
+mod gzip;
 mod header;
 mod io;
+mod limits;
+mod media;
+mod multipart;
+mod percent;
+mod pool;
+mod proxy;
 mod request;
 mod response;
+mod retry;
+mod router;
+mod status;
+mod tls;
+mod transport;
 mod uri;
 mod verb;
+mod version;
 
+pub mod cassette;
 pub mod client;
+pub mod date;
+#[cfg(test)]
+pub mod mock;
+pub mod server;
 
+pub use cookie::{Cookie, CookieJar};
 pub use error::Error;
-pub use header::{Header, HeaderSet};
+pub use form::parse_form;
+pub use header::HeaderSet;
+pub use limits::{Limit, Limits};
+pub use media::MediaType;
+pub use multipart::{parse_multipart, Part};
 pub use request::Request;
 pub use response::Response;
+pub use proxy::ProxyConfig;
+pub use retry::RetryPolicy;
+pub use router::{Router, Params};
+pub use status::StatusCode;
+pub use tls::{TlsConfig, load_identity};
+pub use transport::Transport;
+pub use pool::ThreadPool;
 pub use uri::Protocol;
-pub use uri::URL;
+pub use uri::Url;
 pub use verb::Verb;
-pub use body::{read_body, write_body};
+pub use version::Version;
+pub use body::{read_body, read_until_close, stream_body, write_body, write_chunked, write_until_close, Body, Framing};
+#[cfg(test)]
+pub use body::ChunkReader;
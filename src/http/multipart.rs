@@ -0,0 +1,224 @@
+use crate::http::{Error, HeaderSet, Limits, MediaType};
+use crate::http::media;
+
+/// Longest boundary allowed by RFC 2046 section 5.1.1
+const MAX_BOUNDARY: usize = 70;
+
+/// A part of a multipart/form-data body: the value of a form field, or an
+/// uploaded file
+#[derive(Debug)]
+pub struct Part {
+    pub headers: HeaderSet,
+    /// Name of the form field
+    pub name: String,
+    /// Name of the file on the client, only given for files
+    pub filename: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl Part {
+
+    /// The media type of the part's body, 'text/plain' when it isn't given
+    pub fn content_type(&self) -> MediaType {
+        self.headers.content_type().unwrap_or(MediaType::new("text", "plain"))
+    }
+
+    /// Check if the part is an uploaded file rather than a form field
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Convert the body to a Unicode String
+    pub fn body_string(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Read the headers of a part and keep the rest as its body. The
+    /// 'Content-Disposition' header gives the name of the field
+    fn parse(data: &[u8], limits: &Limits) -> Result<Part, Error> {
+        let mut body = data;
+        let headers = HeaderSet::read(&mut body, limits)?;
+
+        let disposition = headers.get("Content-Disposition")
+            .ok_or(Error::body("Multipart part without Content-Disposition"))?;
+        let (kind, params) = media::parse_params(&disposition)?;
+        if !kind.eq_ignore_ascii_case("form-data") {
+            return Err(Error::body("Multipart part isn't form-data"));
+        }
+
+        let param = |name: &str| params.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.to_string());
+
+        Ok(Part {
+            name: param("name").ok_or(Error::body("Multipart part without a name"))?,
+            filename: param("filename"),
+            headers,
+            body: body.to_vec(),
+        })
+    }
+}
+
+/// Split a multipart/form-data body into its parts, following RFC 7578 and
+/// RFC 2046 section 5.1:
+///
+/// ```text
+/// --boundary CRLF
+/// Content-Disposition: form-data; name="field" CRLF
+/// CRLF
+/// value CRLF
+/// --boundary--
+/// ```
+///
+/// Text before the first boundary and after the last one is ignored. The
+/// headers of every part are limited like those of a request.
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<Part>, Error> {
+    if boundary.is_empty() || boundary.len() > MAX_BOUNDARY {
+        return Err(Error::header("Invalid multipart boundary"));
+    }
+
+    let limits = Limits::default();
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+    // The first boundary doesn't need a line break before it when it starts
+    // the body
+    let mut pos = if body.starts_with(&delimiter[2..]) {
+        delimiter.len() - 2
+    } else {
+        find(body, &delimiter, 0).ok_or(Error::body("Missing multipart boundary"))? + delimiter.len()
+    };
+
+    let mut parts: Vec<Part> = Vec::new();
+
+    loop {
+        // The last boundary is followed by '--'
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+
+        // Whitespace can follow a boundary before the end of its line
+        let end = find(body, b"\r\n", pos).ok_or(Error::body("Unexpected end of multipart body"))?;
+        if !body[pos..end].iter().all(|b| *b == b' ' || *b == b'\t') {
+            return Err(Error::body("Invalid multipart boundary line"));
+        }
+
+        let start = end + 2;
+        let end = find(body, &delimiter, start).ok_or(Error::body("Unexpected end of multipart body"))?;
+        parts.push(Part::parse(&body[start..end], &limits)?);
+        pos = end + delimiter.len();
+    }
+}
+
+/// Find where a sequence of bytes starts, from a position
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|p| p + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{Body, Request, Url, Verb};
+    use super::*;
+
+    /// A form with a text field and a file, separated by a boundary
+    fn form(boundary: &str, file: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "preamble\r\n--{0}\r\n\
+            Content-Disposition: form-data; name=\"to\"\r\n\r\n\
+            4185551234\r\n--{0}  \r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a;b.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n",
+            boundary
+        ).into_bytes();
+        body.extend_from_slice(file);
+        body.extend_from_slice(format!("\r\n--{}--\r\nepilogue", boundary).as_bytes());
+        body
+    }
+
+    #[test]
+    fn parse_fields_and_files() {
+        let parts = parse_multipart(&form("xyz", b"hello"), "xyz").unwrap();
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].name, "to");
+        assert_eq!(parts[0].body_string(), "4185551234");
+        assert!(!parts[0].is_file());
+        assert_eq!(parts[0].content_type().essence(), "text/plain");
+
+        assert_eq!(parts[1].name, "file");
+        assert_eq!(parts[1].filename.as_deref(), Some("a;b.bin"));
+        assert_eq!(parts[1].content_type().essence(), "application/octet-stream");
+        assert_eq!(parts[1].body, b"hello");
+    }
+
+    #[test]
+    fn binary_parts() {
+        // Every byte value, CR LF pairs and something that looks like the
+        // start of a boundary
+        let mut file: Vec<u8> = (0..=255u8).collect();
+        file.extend_from_slice(b"\r\n--xy\r\n--\r\n\0\xFF");
+        let parts = parse_multipart(&form("xyz", &file), "xyz").unwrap();
+        assert_eq!(parts[1].body, file);
+
+        // Empty parts
+        let parts = parse_multipart(&form("xyz", b""), "xyz").unwrap();
+        assert!(parts[1].body.is_empty());
+    }
+
+    #[test]
+    fn body_starting_with_the_boundary() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=a\r\n\r\n1\r\n--xyz--";
+        let parts = parse_multipart(body, "xyz").unwrap();
+        assert_eq!(parts[0].name, "a");
+        assert_eq!(parts[0].body, b"1");
+
+        assert!(parse_multipart(b"--xyz--", "xyz").unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_closing_boundary() {
+        let body = form("xyz", b"hello");
+        let cut = body.len() - b"--\r\nepilogue".len();
+        assert!(parse_multipart(&body[..cut], "xyz").is_err());
+        assert!(parse_multipart(&body[..cut - 5], "xyz").is_err());
+        assert!(parse_multipart(b"no boundary here", "xyz").is_err());
+    }
+
+    #[test]
+    fn invalid_parts() {
+        let body = b"--xyz\r\nContent-Type: text/plain\r\n\r\n1\r\n--xyz--";
+        assert!(parse_multipart(body, "xyz").is_err());
+
+        let body = b"--xyz\r\nContent-Disposition: attachment; name=a\r\n\r\n1\r\n--xyz--";
+        assert!(parse_multipart(body, "xyz").is_err());
+
+        let body = b"--xyz\r\nContent-Disposition: form-data\r\n\r\n1\r\n--xyz--";
+        assert!(parse_multipart(body, "xyz").is_err());
+
+        // Text other than whitespace after a boundary
+        let body = b"--xyzabc\r\nContent-Disposition: form-data; name=a\r\n\r\n1\r\n--xyz--";
+        assert!(parse_multipart(body, "xyz").is_err());
+
+        assert!(parse_multipart(b"----", "").is_err());
+        assert!(parse_multipart(b"", &"x".repeat(71)).is_err());
+    }
+
+    #[test]
+    fn quoted_boundary() {
+        // Boundaries can contain characters that must be quoted in the header
+        let boundary = "---- a:b=c?d ----";
+        let mut request = Request::new(Verb::Post, Url::parse("http://localhost/").unwrap());
+        request.headers.add("Content-Type", &format!("multipart/form-data; boundary=\"{}\"", boundary));
+        request.body = Body::from(form(boundary, b"hello"));
+
+        let parts = request.multipart().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1].body, b"hello");
+
+        let mut request = Request::new(Verb::Post, Url::parse("http://localhost/").unwrap());
+        request.headers.add("Content-Type", "multipart/form-data");
+        assert!(request.multipart().is_err());
+    }
+}
@@ -0,0 +1,61 @@
+/// Percent-encode text so that it can be used in a URL. All characters except
+/// the unreserved ones from RFC 3986 are encoded as UTF-8 bytes, e.g. 'é' => '%C3%A9'
+pub fn encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            },
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+
+    encoded
+}
+
+/// Encode text for an application/x-www-form-urlencoded query or body, where
+/// spaces are encoded as '+'
+pub fn encode_form(text: &str) -> String {
+    text.split(' ')
+        .map(encode)
+        .collect::<Vec<String>>()
+        .join("+")
+}
+
+/// Decode percent-encoded text. Invalid escapes are kept as-is and byte
+/// sequences that aren't valid UTF-8 are replaced with U+FFFD
+pub fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(byte) = decode_hex(bytes[i + 1], bytes[i + 2]) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Decode text from an application/x-www-form-urlencoded query or body, where
+/// '+' is a space
+pub fn decode_form(text: &str) -> String {
+    decode(&text.replace('+', " "))
+}
+
+/// Convert two hexadecimal digits to a byte
+fn decode_hex(high: u8, low: u8) -> Option<u8> {
+    let high = (high as char).to_digit(16)?;
+    let low = (low as char).to_digit(16)?;
+    Some((high * 16 + low) as u8)
+}
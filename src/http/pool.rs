@@ -0,0 +1,118 @@
+use std::io;
+use std::net::TcpStream;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::http::Error;
+
+/// Function called by a worker for every TCP socket dispatched to the pool
+pub type Handler = dyn Fn(TcpStream) + Send + Sync;
+
+/// A fixed-size pool of worker threads handling TCP sockets.
+///
+/// Sockets are placed in a bounded queue. When all workers are busy and the
+/// queue is full, the socket is handed back so the caller can refuse it.
+pub struct ThreadPool {
+    sender: Option<SyncSender<TcpStream>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+
+    /// Start `size` workers sharing a queue that can hold `queue` pending
+    /// sockets. Fails without any worker since nothing would handle the sockets
+    pub fn new(size: usize, queue: usize, handler: Arc<Handler>) -> Result<ThreadPool, Error> {
+        if size == 0 {
+            return Err(Error::from(io::Error::new(io::ErrorKind::InvalidInput, "thread pool needs at least one worker")));
+        }
+
+        let (sender, receiver) = sync_channel::<TcpStream>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|_| ThreadPool::spawn(receiver.clone(), handler.clone()))
+            .collect();
+
+        Ok(ThreadPool {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    /// Start a worker that handles sockets until the pool is dropped
+    fn spawn(receiver: Arc<Mutex<Receiver<TcpStream>>>, handler: Arc<Handler>) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            // The lock is released as soon as a socket is received so that
+            // other workers can wait on the queue while this one is busy
+            let stream = match receiver.lock() {
+                Ok(r) => r.recv(),
+                Err(_) => return
+            };
+
+            // A panic while handling a client must not shrink the pool
+            match stream {
+                Ok(s) => {
+                    let _ = catch_unwind(AssertUnwindSafe(|| handler(s)));
+                },
+                Err(_) => return
+            }
+        })
+    }
+
+    /// Queue a socket for the next available worker. The socket is returned
+    /// back if the queue is full
+    pub fn dispatch(&self, stream: TcpStream) -> Result<(), TcpStream> {
+        let sender = match &self.sender {
+            Some(s) => s,
+            None => return Err(stream)
+        };
+
+        match sender.try_send(stream) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(s)) => Err(s),
+            Err(TrySendError::Disconnected(s)) => Err(s),
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel makes every worker exit once the queue is empty
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use super::*;
+
+    #[test]
+    fn needs_a_worker() {
+        assert!(ThreadPool::new(0, 1, Arc::new(|_| {})).is_err());
+    }
+
+    #[test]
+    fn workers_handle_sockets() {
+        let pool = ThreadPool::new(2, 4, Arc::new(|mut s: TcpStream| {
+            let _ = s.write_all(b"hello");
+        })).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert!(pool.dispatch(stream).is_ok());
+
+        let mut text = String::new();
+        client.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello");
+    }
+}
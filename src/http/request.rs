@@ -1,86 +1,203 @@
-use std::io::BufReader;
+use std::fmt;
+use std::io::BufWriter;
 use std::io::BufRead;
-use std::io::{Read, Write};
+use std::io::Write;
 
 use crate::http;
-use crate::http::Error;
+use crate::http::{Body, Error, Framing, Part};
 use crate::http::Verb;
+use crate::http::Version;
 use crate::http::HeaderSet;
-use crate::http::URL;
+use crate::http::{Limit, Limits};
+use crate::http::io;
+use crate::http::media::is_token;
+use crate::http::Url;
 
 /// Representation for a basic HTTP request
-#[derive(Debug)]
 pub struct Request {
     pub verb: Verb,
-    pub url: URL,
+    pub url: Url,
+    pub version: Version,
     pub headers: HeaderSet,
-    pub body: Vec<u8>
+    /// A streamed body is sent with the chunked method
+    pub body: Body,
+    /// Headers sent after a chunked body, or received after a buffered one
+    pub trailers: HeaderSet,
 }
 
 impl Request {
 
-    pub fn new(verb: Verb, url: URL) -> Request {
+    pub fn new(verb: Verb, url: Url) -> Request {
         Request {
-            verb: verb,
-            url: url,
+            verb,
+            url,
+            version: Version::Http11,
             headers: HeaderSet::new(),
-            body: Vec::new(),
+            body: Body::empty(),
+            trailers: HeaderSet::new(),
         }
     }
 
-    /// Read a HTTP request from a TCP socket
-    pub fn read<T: Read>(stream: &mut T) -> Result<Request, Error> {
-        let mut reader = BufReader::new(stream);
-        let mut request = Request::read_stanza(&mut reader)?;
-        request.headers = HeaderSet::read(&mut reader)?;
-        request.body = http::read_body(&request.headers, &mut reader)?;
-
+    /// Read the request line and headers of a HTTP request, leaving the body
+    /// unread so that it can be streamed
+    pub fn read_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, Error> {
+        let mut request = Request::read_stanza(reader, limits)?;
+        request.headers = HeaderSet::read(reader, limits)?;
         Ok(request)
     }
 
-    /// Write a HTTP request to a TCP socket
-    pub fn write<T: Write>(&self, stream: &mut T) -> Result<(), Error> {
+    /// Write a HTTP request to a TCP socket. A streamed body is consumed
+    pub fn write<T: Write>(&mut self, stream: &mut T) -> Result<(), Error> {
+        // CONNECT asks a proxy for a tunnel to a host and port
+        let target = match self.verb {
+            Verb::Connect => self.url.connection(),
+            _ => self.url.to_query()
+        };
+        self.write_target(&target, stream)
+    }
+
+    /// Write a HTTP request to a proxy, which needs the whole URL instead of
+    /// only the path and arguments. A streamed body is consumed
+    pub fn write_proxied<T: Write>(&mut self, stream: &mut T) -> Result<(), Error> {
+        let target = format!(
+            "{}://{}{}",
+            self.url.protocol.scheme(),
+            self.url.authority(),
+            self.url.to_query(),
+        );
+        self.write_target(&target, stream)
+    }
+
+    /// Write a HTTP request to a TCP socket with the request target, i.e. the
+    /// text between the verb and the version
+    fn write_target<T: Write>(&mut self, target: &str, stream: &mut T) -> Result<(), Error> {
+        // Writing the request in small pieces to a persistent connection
+        // stalls on TCP delayed acknowledgements, so buffer it
+        let mut stream = BufWriter::new(stream);
+
         let stanza = format!(
-            "{} {} HTTP/1.1\r\n", 
+            "{} {} {}\r\n", 
             self.verb.format(), 
-            self.url.to_query(),
+            target,
+            self.version,
         );
-        stream.write(stanza.as_bytes())?;
+        stream.write_all(stanza.as_bytes())?;
 
-        self.headers.write(stream)?;
+        self.headers.write(&mut stream)?;
 
-        if !self.body.is_empty() {
-            http::write_body(&self.body, stream)?;
-        } else {
-            stream.write(b"\r\n")?;
+        // A request body can't end with the connection, so a HTTP/1.0 server
+        // that can't decode the chunked method must be sent the whole body
+        if !self.version.chunked() {
+            self.body.buffer()?;
+        }
+
+        match &self.body {
+            Body::Stream(_) => {
+                let mut reader = self.body.take().into_reader();
+                http::write_chunked(&mut reader, &self.trailers, &mut stream)?;
+            },
+            // Without a length, a server may wait for the body of a POST
+            Body::Buffered(body) if !body.is_empty() || self.verb.has_body() => http::write_body(body, &mut stream)?,
+            Body::Buffered(_) => stream.write_all(b"\r\n")?
         }
 
+        stream.flush()?;
         Ok(())
     }
 
     /// Read and convert the first line of a HTTP request
-    fn read_stanza<T: Read>(reader: &mut BufReader<&mut T>) -> Result<Request, Error> {
-        let mut stanza = String::new();
-        reader.read_line(&mut stanza)?;
+    fn read_stanza<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, Error> {
+        let stanza = io::read_line(reader, limits.max_request_line, Limit::RequestLine)?;
+
+        let mut stanza = stanza.split_whitespace();
+
+        // Only a well-formed line is checked for a method or version that
+        // isn't supported
+        let (verb, query, version) = match (stanza.next(), stanza.next(), stanza.next(), stanza.next()) {
+            (Some(v), Some(q), Some(h), None) if is_token(v) && h.starts_with("HTTP/") => (v, q, h),
+            _ => return Err(Error::RequestLine)
+        };
+
+        let version = Version::parse(version)?;
+        let verb = Verb::parse(verb)?;
+        let url = Url::from_request(query);
+
+        let mut request = Request::new(verb, url);
+        request.version = version;
+        Ok(request)
+    }
 
-        let mut stanza = stanza.trim().split_whitespace();
+    /// Check if the client wants to keep the connection open after the response.
+    /// A request framed both by Transfer-Encoding and Content-Length never does
+    pub fn keep_alive(&self) -> bool {
+        if Framing::is_ambiguous(&self.headers) {
+            return false;
+        }
 
-        let verb = stanza.next().ok_or(Error::Verb)?;
-        let query = stanza.next().ok_or(Error::Query)?;
-        let version = stanza.next().ok_or(Error::Version)?;
+        if self.version.keep_alive() {
+            !self.headers.has_token("Connection", "close")
+        } else {
+            self.headers.has_token("Connection", "keep-alive")
+        }
+    }
+
+    /// Convert the body to a Unicode String. A streamed body is read first
+    pub fn body_string(&mut self) -> Result<String, Error> {
+        Ok(String::from_utf8_lossy(self.body.buffer()?).to_string())
+    }
 
-        if version != "HTTP/1.1" {
-            return Err(Error::Version);
+    /// Read the fields of an application/x-www-form-urlencoded body
+    pub fn form(&mut self) -> Result<Vec<(String, String)>, Error> {
+        match self.headers.content_type() {
+            Some(ref m) if m.essence() == "application/x-www-form-urlencoded" => {
+                Ok(http::parse_form(&self.body_string()?))
+            },
+            _ => Err(Error::header("Expecting an application/x-www-form-urlencoded body"))
         }
+    }
 
-        let verb = Verb::parse(verb)?;
-        let url = URL::from_request(query);
+    /// Read the parts of a multipart/form-data body, e.g. uploaded files
+    pub fn multipart(&mut self) -> Result<Vec<Part>, Error> {
+        let media = self.headers.content_type()
+            .filter(|m| m.essence() == "multipart/form-data")
+            .ok_or(Error::header("Expecting a multipart/form-data body"))?;
+        let boundary = media.param("boundary").ok_or(Error::header("Missing multipart boundary"))?;
+
+        http::parse_multipart(self.body.buffer()?, boundary)
+    }
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("verb", &self.verb)
+            .field("url", &self.url)
+            .field("version", &self.version)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .field("trailers", &self.trailers)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(Request::new(verb, url))
+    fn written(verb: Verb, body: &[u8]) -> String {
+        let mut request = Request::new(verb, Url::parse("http://localhost/sms").unwrap());
+        request.body = body.into();
+        let mut data: Vec<u8> = Vec::new();
+        request.write(&mut data).unwrap();
+        String::from_utf8(data).unwrap()
     }
 
-    /// Convert the body to a Unicode String
-    pub fn body_string(&self) -> String {
-        String::from_utf8_lossy(&self.body).to_string()
+    #[test]
+    fn empty_body_length() {
+        assert!(written(Verb::Post, b"").ends_with("\r\nContent-Length: 0\r\n\r\n"));
+        assert!(written(Verb::Put, b"").ends_with("\r\nContent-Length: 0\r\n\r\n"));
+        assert!(written(Verb::Post, b"a=1").ends_with("\r\nContent-Length: 3\r\n\r\na=1"));
+        assert!(!written(Verb::Get, b"").contains("Content-Length"));
+        assert!(!written(Verb::Delete, b"").contains("Content-Length"));
     }
 }
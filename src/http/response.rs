@@ -1,67 +1,289 @@
-use std::io::BufReader;
+use std::fmt;
+use std::io::BufWriter;
 use std::io::BufRead;
-use std::io::{Read, Write};
+use std::io::Write;
 
 use crate::http;
-use crate::http::{HeaderSet, Error};
+use crate::http::{Limit, Limits};
+use crate::http::encoding;
+use crate::http::io;
+use crate::http::{Body, Cookie, Framing, HeaderSet, Error, StatusCode, Verb, Version};
 
 /// Representation for a basic HTTP response
-#[derive(Debug)]
 pub struct Response {
-    pub code: u32,
+    pub version: Version,
+    pub code: StatusCode,
+    /// Reason phrase sent after the status code. The standard phrase of the
+    /// status code is sent if there is none
+    pub reason: Option<String>,
     pub headers: HeaderSet,
-    pub body: Vec<u8>
+    /// A streamed body is sent with the chunked method, or until the
+    /// connection is closed to a HTTP/1.0 client
+    pub body: Body,
+    /// Headers sent after a chunked body, or received after a buffered one
+    pub trailers: HeaderSet,
+    /// The body was received without a length and ended with the connection
+    close_delimited: bool,
 }
 
 impl Response {
 
-    pub fn new(code: u32, content: &[u8]) -> Response {
+    pub fn new(code: StatusCode, content: &[u8]) -> Response {
         Response {
-            code: code,
+            version: Version::Http11,
+            code,
+            reason: None,
             headers: HeaderSet::new(),
-            body: content.to_vec(),
+            body: Body::from(content),
+            trailers: HeaderSet::new(),
+            close_delimited: false,
         }
     }
 
-    /// Read a HTTP response from a TCP socket
-    pub fn read<T: Read>(stream: &mut T) -> Result<Response, Error> {
-        let mut reader = BufReader::new(stream);
-        let mut response = Response::read_stanza(&mut reader)?;
-        response.headers = HeaderSet::read(&mut reader)?;
-        response.body = http::read_body(&response.headers, &mut reader)?;
+    /// Create a response where the body is read from a stream while it's
+    /// being sent, e.g. a large file
+    #[cfg(test)]
+    pub fn stream<R: std::io::Read + Send + 'static>(code: StatusCode, reader: R) -> Response {
+        let mut response = Response::new(code, &[]);
+        response.body = Body::stream(reader);
+        response
+    }
+
+    /// Create a response where each buffer from the iterator is sent as soon
+    /// as it's produced, e.g. for server-sent events
+    #[cfg(test)]
+    pub fn chunks<I, B>(code: StatusCode, chunks: I) -> Response
+        where I: IntoIterator<Item = B>,
+              I::IntoIter: Send + 'static,
+              B: AsRef<[u8]> + Send + 'static
+    {
+        Response::stream(code, http::ChunkReader::new(chunks.into_iter()))
+    }
+
+    /// Read a HTTP response from a buffered TCP socket. A compressed body is
+    /// decoded
+    #[cfg(test)]
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Response, Error> {
+        Response::read_limited(reader, &Limits::default())
+    }
+
+    /// Read a HTTP response from a buffered TCP socket, failing with
+    /// `Error::TooLarge` if a part of the response exceeds the limits
+    #[cfg(test)]
+    pub fn read_limited<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Response, Error> {
+        let mut response = Response::read_head(reader, limits)?;
+        let has_body = Response::has_body(response.code);
+        response.read_body(reader, limits, has_body)?;
         Ok(response)
     }
 
+    /// Read a HTTP response to a request sent with a verb from a connection,
+    /// leaving the body to be read while it's used. Once it has been read to
+    /// its end, the connection is given to `done` if the server keeps it open.
+    /// Trailers are only known at the end of the body and a compressed body is
+    /// decoded as a whole, so those bodies are read right away
+    pub fn read_streamed<R, F>(mut reader: R, verb: Verb, limits: &Limits, done: F) -> Result<Response, Error>
+        where R: BufRead + Send + 'static,
+              F: FnOnce(R) + Send + 'static
+    {
+        let mut response = Response::read_head(&mut reader, limits)?;
+
+        // The headers of a response to HEAD describe the body a GET would get
+        let has_body = verb != Verb::Head && Response::has_body(response.code);
+
+        if has_body && (response.headers.get("Trailer").is_some() || response.headers.get("Content-Encoding").is_some()) {
+            // Decoding the body removes its Content-Length
+            let keep_alive = response.keep_alive();
+            response.read_body(&mut reader, limits, has_body)?;
+            if keep_alive && response.keep_alive() {
+                done(reader);
+            }
+            return Ok(response);
+        }
+
+        let mut framing = match has_body {
+            true => Framing::from_headers(&response.headers, limits)?,
+            false => Framing::Empty
+        };
+        if framing == Framing::Empty && has_body {
+            framing = Framing::Close;
+            response.close_delimited = true;
+        }
+
+        let keep_alive = response.keep_alive();
+        response.body = http::stream_body(reader, framing, limits, move |r, complete| {
+            if complete && keep_alive {
+                done(r);
+            }
+        });
+
+        Ok(response)
+    }
+
+    /// Read the body following the headers, and its trailers, unless the
+    /// response has none. A compressed body is decoded
+    fn read_body<R: BufRead>(&mut self, reader: &mut R, limits: &Limits, has_body: bool) -> Result<(), Error> {
+        if !has_body {
+            self.body = Body::empty();
+            return Ok(());
+        }
+
+        // Without a length, the body ends when the server closes the connection
+        let framed = self.headers.get("Transfer-Encoding").is_some()
+            || self.headers.get("Content-Length").is_some();
+
+        let body = if !framed {
+            self.close_delimited = true;
+            http::read_until_close(reader, limits)?
+        } else {
+            let (body, trailers) = http::read_body(&self.headers, reader, limits)?;
+            self.trailers = trailers;
+            body
+        };
+
+        let body = encoding::decode(&mut self.headers, body, limits.max_body_size)?;
+        self.body = Body::Buffered(body);
+
+        Ok(())
+    }
+
+    /// Read the status line and headers of a HTTP response, leaving the body
+    /// unread, e.g. for a response to CONNECT
+    pub fn read_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Response, Error> {
+        let mut response = Response::read_stanza(reader, limits)?;
+        response.headers = HeaderSet::read(reader, limits)?;
+        Ok(response)
+    }
+
+    /// Check if a response with a status code can have a body. Informational,
+    /// 204 No Content and 304 Not Modified responses never do
+    fn has_body(code: StatusCode) -> bool {
+        !code.is_informational() && code != StatusCode::NO_CONTENT && code != StatusCode::NOT_MODIFIED
+    }
+
     /// Read and convert the first line of a HTTP response
-    fn read_stanza<T: Read>(reader: &mut BufReader<&mut T>) -> Result<Response, Error> {
-        let mut stanza = String::new();
-        reader.read_line(&mut stanza)?;
+    fn read_stanza<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Response, Error> {
+        let stanza = io::read_line(reader, limits.max_header_line, Limit::HeaderLine)?;
+
+        // The reason phrase is optional and can contain spaces
+        let mut stanza = stanza.splitn(3, ' ');
+        let version = Version::parse(stanza.next().ok_or(Error::Version)?)?;
+        let code = StatusCode::parse(stanza.next().ok_or(Error::Status)?)?;
+
+        let mut response = Response::new(code, &[]);
+        response.version = version;
+        response.reason = stanza.next().map(|r| r.trim().to_string());
+        Ok(response)
+    }
+
+    /// Write the response to a TCP socket. A streamed body is consumed.
+    /// Informational, 204 No Content and 304 Not Modified responses are
+    /// written without their body
+    pub fn write<T: Write>(&mut self, stream: &mut T) -> Result<(), Error> {
+        self.write_message(stream, false)
+    }
+
+    /// Write the response to a HEAD request to a TCP socket: the headers
+    /// describe the body, but it isn't sent
+    pub fn write_head<T: Write>(&mut self, stream: &mut T) -> Result<(), Error> {
+        self.write_message(stream, true)
+    }
+
+    fn write_message<T: Write>(&mut self, stream: &mut T, head: bool) -> Result<(), Error> {
+        // Writing the response in small pieces to a persistent connection
+        // stalls on TCP delayed acknowledgements, so buffer it
+        let mut stream = BufWriter::new(stream);
 
-        if !stanza.trim().starts_with("HTTP/1.1") {
-            return Err(Error::Version);
+        let reason = match &self.reason {
+            Some(r) => r.as_str(),
+            None => self.code.reason().unwrap_or("")
+        };
+        let stanza = format!("{} {} {}\r\n", self.version, self.code, reason);
+        stream.write_all(stanza.as_bytes())?;
+
+        self.headers.write(&mut stream)?;
+
+        if !Response::has_body(self.code) {
+            self.body = Body::empty();
+            stream.write_all(b"\r\n")?;
+        } else if head {
+            match &self.body {
+                Body::Stream(_) if self.version.chunked() => stream.write_all(b"Transfer-Encoding: chunked\r\n\r\n")?,
+                Body::Stream(_) => stream.write_all(b"\r\n")?,
+                Body::Buffered(body) => stream.write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())?
+            }
+            self.body = Body::empty();
+        } else {
+            match &self.body {
+                Body::Stream(_) => {
+                    let mut reader = self.body.take().into_reader();
+                    if self.version.chunked() {
+                        http::write_chunked(&mut reader, &self.trailers, &mut stream)?
+                    } else {
+                        http::write_until_close(&mut reader, &mut stream)?
+                    }
+                },
+                Body::Buffered(body) => http::write_body(body, &mut stream)?
+            }
+        }
+
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Check if the server will keep the connection open for another request.
+    /// A body received without a length, or sent with an unknown length to a
+    /// HTTP/1.0 client, ends with the connection. So does a response framed
+    /// both by Transfer-Encoding and Content-Length
+    pub fn keep_alive(&self) -> bool {
+        if self.close_delimited || (self.body.is_stream() && !self.version.chunked()) {
+            return false;
         }
 
-        let mut stanza = stanza.split_whitespace();
-        let code = stanza
-            .nth(1)
-            .ok_or(Error::Status)?
-            .parse::<u32>()
-            .map_err(|_| Error::Status)?;
+        if Framing::is_ambiguous(&self.headers) {
+            return false;
+        }
 
-        Ok(Response::new(code, &[]))
+        if self.version.keep_alive() {
+            !self.headers.has_token("Connection", "close")
+        } else {
+            self.headers.has_token("Connection", "keep-alive")
+        }
     }
 
-    /// Write the response to a TCP socket
-    pub fn write<T: Write>(&self, stream: &mut T) -> Result<(), Error> {
-        let stanza = format!("HTTP/1.1 {}\r\n", self.code);
-        stream.write(stanza.as_bytes())?;
+    /// Convert the body to a Unicode String. A streamed body is read first
+    pub fn body_string(&mut self) -> Result<String, Error> {
+        Ok(String::from_utf8_lossy(self.body.buffer()?).to_string())
+    }
 
-        self.headers.write(stream)?;
-        http::write_body(&self.body, stream)
+    /// Ask the client to keep a cookie with a 'Set-Cookie' header. Fails if
+    /// the cookie's name, value or attributes can't be sent as-is
+    #[cfg(test)]
+    pub fn set_cookie(&mut self, cookie: &Cookie) -> Result<(), Error> {
+        cookie.validate()?;
+        self.headers.add("Set-Cookie", &cookie.to_string());
+        Ok(())
     }
 
-    pub fn body_string(&self) -> String {
-        String::from_utf8_lossy(&self.body).to_string()
+    /// The cookies set by the server. Invalid ones are skipped
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.headers.get_all("Set-Cookie")
+            .iter()
+            .filter_map(|h| Cookie::parse(h).ok())
+            .collect()
     }
 
 }
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("version", &self.version)
+            .field("code", &self.code)
+            .field("reason", &self.reason)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .field("trailers", &self.trailers)
+            .finish()
+    }
+}
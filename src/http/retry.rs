@@ -0,0 +1,166 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+use crate::http::{Error, Response, StatusCode, Verb};
+use crate::http::date;
+
+/// When and how often the HTTP client retries a request that failed.
+///
+/// Delays grow exponentially: base_delay, 2 * base_delay, 4 * base_delay, etc.
+/// up to max_delay. A 'Retry-After' header sent by the server replaces the
+/// computed delay, but is still limited to max_delay.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+    /// Randomly shorten delays by up to half so that many clients failing
+    /// at the same time don't all retry at the same time
+    pub jitter: bool,
+    /// Response status codes that are retried
+    pub statuses: Vec<StatusCode>,
+    /// Also retry requests that are not idempotent, e.g. POST. The server may
+    /// have processed the request even if it failed.
+    pub retry_non_idempotent: bool,
+    /// Also retry failures that can happen once the request was sent, e.g. a
+    /// timeout waiting for the response. Failures to connect are always retried
+    pub retry_sent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            statuses: vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_non_idempotent: false,
+            retry_sent: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+
+    /// Only retry requests that never reached the server, for requests that
+    /// must not be processed twice, e.g. one sending a message
+    pub fn connect_only() -> RetryPolicy {
+        RetryPolicy {
+            statuses: Vec::new(),
+            retry_sent: false,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Check if a request can be retried at all with this policy
+    pub fn applies_to(&self, verb: Verb) -> bool {
+        verb.is_idempotent() || self.retry_non_idempotent
+    }
+
+    /// Check if the result of an attempt is a transient failure worth retrying
+    pub fn should_retry(&self, result: &Result<Response, Error>) -> bool {
+        match result {
+            Ok(response) => self.statuses.contains(&response.code),
+            Err(Error::Connect(_)) => true,
+            Err(Error::Io(_)) | Err(Error::Timeout) => self.retry_sent,
+            Err(_) => false
+        }
+    }
+
+    /// How long to wait before the next attempt. `attempt` is the number of
+    /// attempts done so far, starting at 1
+    pub fn delay(&self, attempt: u32, result: &Result<Response, Error>) -> Duration {
+        if let Ok(response) = result {
+            if let Some(delay) = RetryPolicy::retry_after(response) {
+                return delay.min(self.max_delay);
+            }
+        }
+
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if self.jitter {
+            delay.mul_f64(1.0 - random() / 2.0)
+        } else {
+            delay
+        }
+    }
+
+    /// Read the delay asked by the server in a 'Retry-After' header, either
+    /// a number of seconds or an HTTP date
+    fn retry_after(response: &Response) -> Option<Duration> {
+        let value = response.headers.get("Retry-After")?;
+        let value = value.trim();
+
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+
+        let date = date::parse(value)?;
+        let delay = date - Utc::now();
+        Some(delay.to_std().unwrap_or(Duration::from_secs(0)))
+    }
+}
+
+/// A random number between 0 and 1. The standard library doesn't have a
+/// random number generator, but its hash maps are seeded randomly
+fn random() -> f64 {
+    let value = RandomState::new().build_hasher().finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::*;
+
+    fn refused() -> Result<Response, Error> {
+        Err(Error::Connect(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")))
+    }
+
+    #[test]
+    fn default_retries_transient_failures() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(&refused()));
+        assert!(policy.should_retry(&Err(Error::Timeout)));
+        assert!(policy.should_retry(&Err(Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset")))));
+        assert!(policy.should_retry(&Ok(Response::new(StatusCode::SERVICE_UNAVAILABLE, b""))));
+        assert!(!policy.should_retry(&Ok(Response::new(StatusCode::OK, b""))));
+        assert!(!policy.should_retry(&Err(Error::Status)));
+    }
+
+    #[test]
+    fn connect_only_never_resends() {
+        let policy = RetryPolicy::connect_only();
+        assert!(policy.should_retry(&refused()));
+        assert!(!policy.should_retry(&Err(Error::Timeout)));
+        assert!(!policy.should_retry(&Err(Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset")))));
+        assert!(!policy.should_retry(&Ok(Response::new(StatusCode::SERVICE_UNAVAILABLE, b""))));
+    }
+
+    #[test]
+    fn delay_grows_up_to_max() {
+        let policy = RetryPolicy { jitter: false, ..RetryPolicy::default() };
+        assert_eq!(policy.delay(1, &Err(Error::Timeout)), Duration::from_millis(200));
+        assert_eq!(policy.delay(3, &Err(Error::Timeout)), Duration::from_millis(800));
+        assert_eq!(policy.delay(10, &Err(Error::Timeout)), Duration::from_secs(5));
+
+        let mut response = Response::new(StatusCode::SERVICE_UNAVAILABLE, b"");
+        response.headers.add("Retry-After", "2");
+        assert_eq!(policy.delay(1, &Ok(response)), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::http::{Request, Response, StatusCode, Verb};
+use crate::http::percent;
+
+/// Function called when a request matches a route. The request's body is
+/// read from the client as the handler uses it
+pub type Handler = dyn Fn(&mut Request, &Params) -> Response + Send + Sync;
+
+/// Part of a route's path, e.g. 'stops' or '{stop}' in '/stops/{stop}'
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// A handler registered for a verb and path pattern
+struct Route {
+    verb: Verb,
+    pattern: Vec<Segment>,
+    handler: Box<Handler>,
+    /// A GET route also answers HEAD requests
+    head: bool,
+}
+
+/// Values captured from the path parameters of a route
+#[derive(Debug)]
+pub struct Params {
+    params: Vec<(String, String)>
+}
+
+impl Params {
+
+    /// Get the value captured for a path parameter, e.g. 'stop' for '/stops/{stop}'
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Dispatch HTTP requests to handlers based on the verb and path.
+///
+/// Paths are patterns where a segment between braces captures a path
+/// parameter, e.g. '/stops/{stop}/buses/{bus}'. Requests that don't match any
+/// path get a 404, requests that match a path but not its verbs get a 405
+/// with the verbs it allows.
+///
+/// HEAD requests are handled by the GET handler unless HEAD has its own, the
+/// server then leaves out the body. GET routes registered with `get_only`
+/// don't answer HEAD, for handlers with side effects. OPTIONS requests are
+/// answered with the verbs allowed for the path unless OPTIONS has its own
+/// handler.
+pub struct Router {
+    routes: Vec<Route>
+}
+
+impl Router {
+
+    pub fn new() -> Router {
+        Router { routes: Vec::new() }
+    }
+
+    /// Register a handler for a verb and path pattern
+    pub fn add<F>(&mut self, verb: Verb, pattern: &str, handler: F)
+        where F: Fn(&mut Request, &Params) -> Response + Send + Sync + 'static
+    {
+        self.routes.push(Route {
+            verb,
+            pattern: Router::parse_pattern(pattern),
+            handler: Box::new(handler),
+            head: true,
+        });
+    }
+
+    /// Register a handler for GET requests
+    pub fn get<F>(&mut self, pattern: &str, handler: F)
+        where F: Fn(&mut Request, &Params) -> Response + Send + Sync + 'static
+    {
+        self.add(Verb::Get, pattern, handler);
+    }
+
+    /// Register a handler for GET requests that doesn't also answer HEAD
+    /// requests, e.g. one that sends a message
+    pub fn get_only<F>(&mut self, pattern: &str, handler: F)
+        where F: Fn(&mut Request, &Params) -> Response + Send + Sync + 'static
+    {
+        self.add(Verb::Get, pattern, handler);
+        if let Some(route) = self.routes.last_mut() {
+            route.head = false;
+        }
+    }
+
+    /// Register a handler for POST requests
+    pub fn post<F>(&mut self, pattern: &str, handler: F)
+        where F: Fn(&mut Request, &Params) -> Response + Send + Sync + 'static
+    {
+        self.add(Verb::Post, pattern, handler);
+    }
+
+    /// Find the route for a request and call its handler
+    pub fn handle(&self, request: &mut Request) -> Response {
+        let path = Router::split_path(&request.url.path);
+
+        if let Some((route, params)) = self.find(request.verb, &path) {
+            return (route.handler)(request, &params);
+        }
+
+        if request.verb == Verb::Head {
+            if let Some((route, params)) = self.find(Verb::Get, &path) {
+                if route.head {
+                    return (route.handler)(request, &params);
+                }
+            }
+        }
+
+        let allowed = self.allowed(&path);
+        if allowed.is_empty() {
+            return Response::new(StatusCode::NOT_FOUND, b"Not found\n");
+        }
+
+        let allow: Vec<String> = allowed.iter().map(|v| v.format()).collect();
+        let mut response = if request.verb == Verb::Options {
+            Response::new(StatusCode::NO_CONTENT, b"")
+        } else {
+            Response::new(StatusCode::METHOD_NOT_ALLOWED, b"Method not allowed\n")
+        };
+        response.headers.add("Allow", &allow.join(", "));
+        response
+    }
+
+    /// Find the route registered for a verb that matches a path
+    fn find(&self, verb: Verb, path: &[&str]) -> Option<(&Route, Params)> {
+        self.routes.iter()
+            .filter(|r| r.verb == verb)
+            .filter_map(|r| Router::match_path(&r.pattern, path).map(|p| (r, p)))
+            .next()
+    }
+
+    /// List the verbs that can be used with a path, in the order their routes
+    /// were registered. HEAD is allowed along with GET unless the route was
+    /// registered with `get_only`, and OPTIONS is always allowed for a path
+    /// that exists
+    fn allowed(&self, path: &[&str]) -> Vec<Verb> {
+        let mut allowed: Vec<Verb> = Vec::new();
+
+        for route in self.routes.iter() {
+            if Router::match_path(&route.pattern, path).is_none() {
+                continue;
+            }
+
+            let mut verbs = vec![route.verb];
+            if route.verb == Verb::Get && route.head {
+                verbs.push(Verb::Head);
+            }
+
+            for verb in verbs {
+                if !allowed.contains(&verb) {
+                    allowed.push(verb);
+                }
+            }
+        }
+
+        if !allowed.is_empty() && !allowed.contains(&Verb::Options) {
+            allowed.push(Verb::Options);
+        }
+
+        allowed
+    }
+
+    /// Convert a path pattern to its list of segments
+    fn parse_pattern(pattern: &str) -> Vec<Segment> {
+        Router::split_path(pattern)
+            .into_iter()
+            .map(|s| {
+                if s.starts_with('{') && s.ends_with('}') {
+                    Segment::Param(s[1..s.len()-1].to_string())
+                } else {
+                    Segment::Literal(s.to_string())
+                }
+            })
+            .collect()
+    }
+
+    /// Split a path on '/', ignoring empty segments so that trailing slashes
+    /// don't matter
+    fn split_path(path: &str) -> Vec<&str> {
+        path.split('/')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// Compare a path to a pattern, returning the path parameters if it
+    /// matches. Parameters are decoded like query arguments, but a '+' stays
+    /// a '+' in a path
+    fn match_path(pattern: &[Segment], path: &[&str]) -> Option<Params> {
+        if pattern.len() != path.len() {
+            return None;
+        }
+
+        let mut params: Vec<(String, String)> = Vec::new();
+        for (segment, part) in pattern.iter().zip(path.iter()) {
+            match segment {
+                Segment::Literal(l) if l == part => {},
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => params.push((name.to_string(), percent::decode(part)))
+            }
+        }
+
+        Some(Params { params })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::http::Url;
+    use super::*;
+
+    fn request(verb: Verb, path: &str) -> Request {
+        Request::new(verb, Url::parse(&format!("http://localhost{}", path)).unwrap())
+    }
+
+    #[test]
+    fn head_uses_get_handler() {
+        let mut router = Router::new();
+        router.get("/stops/{stop}", |_, params| Response::new(StatusCode::OK, params.get("stop").unwrap().as_bytes()));
+
+        let mut response = router.handle(&mut request(Verb::Head, "/stops/1234"));
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "1234");
+
+        let response = router.handle(&mut request(Verb::Options, "/stops/1234"));
+        assert_eq!(response.code, StatusCode::NO_CONTENT);
+        assert_eq!(response.headers.get("Allow").as_deref(), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn params_are_decoded() {
+        let mut router = Router::new();
+        router.get("/stops/{stop}", |_, params| Response::new(StatusCode::OK, params.get("stop").unwrap().as_bytes()));
+
+        let mut response = router.handle(&mut request(Verb::Get, "/stops/Place%20d%27Youville%2FSaint-Jean+%C3%89"));
+        assert_eq!(response.body_string().unwrap(), "Place d'Youville/Saint-Jean+\u{c9}");
+    }
+
+    #[test]
+    fn get_only_does_not_answer_head() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+
+        let mut router = Router::new();
+        router.get_only("/", move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Response::new(StatusCode::OK, b"sent")
+        });
+        router.post("/", |_, _| Response::new(StatusCode::OK, b"sent"));
+
+        let response = router.handle(&mut request(Verb::Head, "/"));
+        assert_eq!(response.code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow").as_deref(), Some("GET, POST, OPTIONS"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let response = router.handle(&mut request(Verb::Get, "/"));
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unknown_path_and_verb() {
+        let mut router = Router::new();
+        router.post("/", |_, _| Response::new(StatusCode::OK, b""));
+
+        assert_eq!(router.handle(&mut request(Verb::Get, "/missing")).code, StatusCode::NOT_FOUND);
+
+        let response = router.handle(&mut request(Verb::Put, "/"));
+        assert_eq!(response.code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow").as_deref(), Some("POST, OPTIONS"));
+    }
+}
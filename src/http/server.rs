@@ -0,0 +1,594 @@
+extern crate native_tls;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http;
+use crate::http::{Body, Framing, Request, Response, Error, Limit, Limits, Router, StatusCode, ThreadPool, Verb, Version};
+use crate::http::encoding;
+use crate::http::io::{ReadWrite, TimedStream};
+use native_tls::{Identity, TlsAcceptor};
+
+/// Settings for the HTTP server
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Number of worker threads handling clients
+    pub workers: usize,
+    /// Number of clients waiting for a worker before new ones are refused
+    pub queue: usize,
+    /// How long a connection is kept open waiting for the client's next request
+    pub idle_timeout: Duration,
+    /// How long a client has to send the request line and headers once it
+    /// started, and to receive the complete response. The body can take
+    /// longer as long as the client keeps up with `min_body_rate`. Streamed
+    /// responses can take longer, as long as no single write blocks for this long
+    pub request_timeout: Duration,
+    /// Least number of bytes of a request body a client must send in every
+    /// `request_timeout`. A client sending its body slower gets a 408
+    pub min_body_rate: usize,
+    /// Compress response bodies of at least this many bytes when the client
+    /// accepts it. `None` never compresses responses
+    pub compress_min_size: Option<usize>,
+    /// Largest requests accepted from clients
+    pub limits: Limits,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            workers: 4,
+            queue: 32,
+            idle_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            min_body_rate: 1024,
+            compress_min_size: None,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// How long a client refused by a busy server has to read the 503 and
+/// finish sending its request, since this blocks accepting new clients
+const BUSY_LINGER: Duration = Duration::from_millis(100);
+
+/// A connection to a client, handed back and forth between the server and
+/// the body of the request being handled
+type Connection = BufReader<TimedStream>;
+
+/// HTTP server that accepts clients and dispatches their requests to a router
+pub struct Server {
+    router: Arc<Router>,
+    config: ServerConfig,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Server {
+
+    pub fn new(router: Router, config: ServerConfig) -> Server {
+        Server {
+            router: Arc::new(router),
+            config,
+            tls: None,
+        }
+    }
+
+    /// Serve HTTPS instead of HTTP, using an identity made of the server's
+    /// certificate and its private key
+    pub fn with_tls(mut self, identity: Identity) -> Result<Server, Error> {
+        self.tls = Some(TlsAcceptor::new(identity)?);
+        Ok(self)
+    }
+
+    /// Listen for clients on a host, e.g. '0.0.0.0:8080'. Only returns if the
+    /// host cannot be bound or the server can't start
+    pub fn listen(&self, host: &str) -> Result<(), Error> {
+        let listener = TcpListener::bind(host)?;
+        self.serve(listener)
+    }
+
+    /// Serve the clients of a listener that is already bound. Only returns if
+    /// the server can't start, e.g. without any worker
+    pub fn serve(&self, listener: TcpListener) -> Result<(), Error> {
+        let router = self.router.clone();
+        let config = self.config.clone();
+        let tls = self.tls.clone();
+        let pool = ThreadPool::new(self.config.workers, self.config.queue, Arc::new(move |s| {
+            if let Err(e) = Server::handle_client(&router, &config, tls.as_ref(), s) {
+                println!("ERROR: {}", e);
+            }
+        }))?;
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    if let Err(s) = pool.dispatch(s) {
+                        if let Err(e) = Server::handle_busy(s, self.tls.is_some()) {
+                            println!("ERROR: {}", e);
+                        }
+                    }
+                },
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read requests from a client, route them and write back the responses
+    /// until the client closes the connection or stays idle for too long
+    fn handle_client(router: &Router, config: &ServerConfig, tls: Option<&TlsAcceptor>, stream: TcpStream)
+        -> Result<(), Error>
+    {
+        let stream = Server::accept(config, tls, stream)?;
+
+        // The request's body owns the connection while the handler reads it,
+        // and gives it back here once it has been read, telling if it was
+        // read to its end
+        let slot: Arc<Mutex<Option<(Connection, bool)>>> = Arc::new(Mutex::new(None));
+        let timeout = Some(config.request_timeout);
+        let mut reader = BufReader::new(TimedStream::new(stream, timeout, timeout));
+
+        loop {
+            // An empty buffer means the client closed the connection. A
+            // timeout means it stopped sending requests, both are normal
+            // ways for a persistent connection to end.
+            reader.get_mut().set_deadline(Some(Instant::now() + config.idle_timeout));
+            match reader.fill_buf() {
+                Ok([]) => return Ok(()),
+                Ok(_) => {},
+                Err(_) => return Ok(())
+            }
+
+            // A client sending its request very slowly would keep a worker
+            // busy forever without a deadline
+            reader.get_mut().reset_timed_out();
+            reader.get_mut().set_min_read_rate(None);
+            reader.get_mut().set_deadline(Some(Instant::now() + config.request_timeout));
+            let (mut request, framing) = match Server::read_request(&mut reader, &config.limits) {
+                Ok(r) => r,
+                Err(Error::Timeout) => {
+                    reader.get_mut().set_deadline(Some(Instant::now() + config.request_timeout));
+                    Server::reject(reader.get_mut(), StatusCode::REQUEST_TIMEOUT, "Request timeout")?;
+                    return Err(Error::Timeout);
+                },
+                Err(Error::Verb) => {
+                    Server::reject(reader.get_mut(), StatusCode::NOT_IMPLEMENTED, "Method not implemented")?;
+                    return Err(Error::Verb);
+                },
+                Err(Error::Version) => {
+                    Server::reject(reader.get_mut(), StatusCode::HTTP_VERSION_NOT_SUPPORTED, "HTTP version not supported")?;
+                    return Err(Error::Version);
+                },
+                Err(Error::TooLarge(limit)) => {
+                    let code = match limit {
+                        Limit::RequestLine => StatusCode::URI_TOO_LONG,
+                        Limit::Headers | Limit::HeaderLine => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                        Limit::Body => StatusCode::CONTENT_TOO_LARGE,
+                    };
+                    Server::reject(reader.get_mut(), code, &limit.to_string())?;
+                    return Err(Error::TooLarge(limit));
+                },
+                Err(e) => {
+                    Server::reject(reader.get_mut(), StatusCode::BAD_REQUEST, "Bad request")?;
+                    return Err(e);
+                }
+            };
+
+            // The body is only read once the handler uses it, which can take
+            // longer than the deadline, e.g. a large upload. The client must
+            // still keep sending it at a minimum rate
+            reader.get_mut().set_deadline(None);
+            reader.get_mut().set_min_read_rate(Some((config.min_body_rate, config.request_timeout)));
+            println!("request: {} {}", request.verb, request.url.to_query());
+
+            let returned = slot.clone();
+            let body = http::stream_body(reader, framing, &config.limits, move |r, complete| {
+                if let Ok(mut slot) = returned.lock() {
+                    *slot = Some((r, complete));
+                }
+            });
+
+            // Unless it was already read for its trailers
+            if framing != Framing::Empty {
+                request.body = body;
+            }
+
+            let mut response = router.handle(&mut request);
+
+            // Skip what the handler didn't read of the body to get the
+            // connection back. A handler can also send the body back in its
+            // response, which is then buffered first
+            request.body = Body::empty();
+            let mut connection = Server::take(&slot);
+            if connection.is_none() && response.body.is_stream() {
+                response.body.buffer()?;
+                connection = Server::take(&slot);
+            }
+            let (connection, complete) = connection.ok_or(Error::body("Request body wasn't read to its end"))?;
+            reader = connection;
+            reader.get_mut().set_min_read_rate(None);
+
+            // Whatever the handler answered, it didn't get the whole body
+            if reader.get_ref().timed_out() {
+                response = Response::new(StatusCode::REQUEST_TIMEOUT, b"Request timeout\n");
+            }
+
+            // Answer in the client's version so that a HTTP/1.0 client isn't
+            // sent a chunked body. It only keeps the connection open if it
+            // asked for it and if the response tells it so
+            response.version = request.version;
+            if request.version == Version::Http10 && request.keep_alive() {
+                response.headers.add_default("Connection", "keep-alive");
+            }
+
+            // What's left of a body that couldn't be read can't be told
+            // apart from the next request
+            let keep_alive = complete && request.keep_alive() && response.keep_alive();
+            if !keep_alive {
+                response.headers.set("Connection", "close");
+            }
+
+            println!("response: {} '{}'",
+                response.code,
+                response.body
+            );
+            if let Some(size) = config.compress_min_size {
+                Server::compress(&request, &mut response, size);
+            }
+
+            // A streamed body, e.g. server-sent events, can be sent for as
+            // long as the client keeps reading it
+            if request.verb != Verb::Head && response.body.is_stream() {
+                reader.get_mut().set_deadline(None);
+            } else {
+                reader.get_mut().set_deadline(Some(Instant::now() + config.request_timeout));
+            }
+
+            if request.verb == Verb::Head {
+                response.write_head(reader.get_mut())?;
+            } else {
+                response.write(reader.get_mut())?;
+            }
+
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read the request line and headers of a request, and find how its body
+    /// is framed. A body announcing trailers is read right away since they
+    /// only come at its end
+    fn read_request(reader: &mut Connection, limits: &Limits) -> Result<(Request, Framing), Error> {
+        let mut request = Request::read_head(reader, limits)?;
+        let framing = Framing::from_headers(&request.headers, limits)?;
+
+        if request.headers.get("Trailer").is_some() {
+            let (body, trailers) = http::read_body(&request.headers, reader, limits)?;
+            request.body = Body::Buffered(body);
+            request.trailers = trailers;
+            return Ok((request, Framing::Empty));
+        }
+
+        Ok((request, framing))
+    }
+
+    /// Take the connection once the request's body gave it back
+    fn take(slot: &Mutex<Option<(Connection, bool)>>) -> Option<(Connection, bool)> {
+        slot.lock().ok().and_then(|mut s| s.take())
+    }
+
+    /// Do the TLS handshake with a client when serving HTTPS. Each read and
+    /// write of the handshake has as long as a whole request
+    fn accept(config: &ServerConfig, tls: Option<&TlsAcceptor>, stream: TcpStream) -> Result<Box<dyn ReadWrite>, Error> {
+        let acceptor = match tls {
+            Some(a) => a,
+            None => return Ok(Box::new(stream))
+        };
+
+        stream.set_read_timeout(Some(config.request_timeout))?;
+        stream.set_write_timeout(Some(config.request_timeout))?;
+        let stream = acceptor.accept(stream)?;
+        Ok(Box::new(stream))
+    }
+
+    /// Answer a request that couldn't be read, and close the connection since
+    /// the rest of the request can't be told apart from the next one
+    fn reject<T: Write>(stream: &mut T, code: StatusCode, message: &str) -> Result<(), Error> {
+        let body = format!("{}\n", message);
+        let mut response = Response::new(code, body.as_bytes());
+        response.headers.add("Connection", "close");
+        response.write(stream)
+    }
+
+    /// Compress the body of a response with the coding preferred by the
+    /// client. Small bodies aren't worth it, and streamed bodies are sent as-is
+    fn compress(request: &Request, response: &mut Response, min_size: usize) {
+        let size = match &response.body {
+            Body::Buffered(body) => body.len(),
+            Body::Stream(_) => return
+        };
+
+        if size < min_size || response.headers.get("Content-Encoding").is_some() {
+            return;
+        }
+
+        // Images, audio and video are already compressed
+        if let Some(media) = response.headers.content_type() {
+            if media.kind == "image" || media.kind == "audio" || media.kind == "video" {
+                return;
+            }
+        }
+
+        // Caches must know that the body depends on the client's header
+        response.headers.add("Vary", "Accept-Encoding");
+
+        let coding = match encoding::preferred(&request.headers) {
+            Some(c) => c,
+            None => return
+        };
+
+        let encoded = match &response.body {
+            Body::Buffered(body) => encoding::encode(coding, body),
+            Body::Stream(_) => None
+        };
+
+        if let Some(body) = encoded {
+            response.body = Body::Buffered(body);
+            response.headers.set("Content-Encoding", coding);
+        }
+    }
+
+    /// Refuse a client when all workers are busy and the queue is full. A
+    /// HTTPS client is disconnected since answering would need a handshake
+    fn handle_busy(mut stream: TcpStream, tls: bool) -> Result<(), Error> {
+        println!("ERROR: too many pending requests, refusing client");
+
+        if tls {
+            return Ok(());
+        }
+
+        stream.set_write_timeout(Some(BUSY_LINGER))?;
+
+        let mut response = Response::new(StatusCode::SERVICE_UNAVAILABLE, b"Server busy, try again later\n");
+        response.headers.add("Connection", "close");
+        response.headers.add("Retry-After", "1");
+        response.write(&mut stream)?;
+
+        // Closing a socket with unread data resets the connection, and the
+        // client could lose the response. Read its request until it closes
+        stream.shutdown(Shutdown::Write)?;
+        let deadline = Instant::now() + BUSY_LINGER;
+        let mut buffer = [0; 4096];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+
+            stream.set_read_timeout(Some(left))?;
+            match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+
+    /// Start a server on a free port and connect to it
+    fn start(router: Router, config: ServerConfig) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(router, config);
+        thread::spawn(move || server.serve(listener));
+        TcpStream::connect(address).unwrap()
+    }
+
+    fn timeout(request_timeout: Duration) -> ServerConfig {
+        ServerConfig { request_timeout, ..ServerConfig::default() }
+    }
+
+    #[test]
+    fn invalid_head_is_bad_request() {
+        let mut router = Router::new();
+        router.get("/", |_, _| Response::new(StatusCode::OK, b"OK"));
+
+        let mut stream = start(router, ServerConfig::default());
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nNo colon here\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let response = Response::read_from(&mut reader).unwrap();
+        assert_eq!(response.code, StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers.get("Connection").as_deref(), Some("close"));
+    }
+
+    #[test]
+    fn request_line_errors() {
+        let cases: [(&[u8], StatusCode); 6] = [
+            (b"GET /\r\n\r\n", StatusCode::BAD_REQUEST),
+            (b"hello\r\n\r\n", StatusCode::BAD_REQUEST),
+            (b"GET / HTTP/1.1 extra\r\n\r\n", StatusCode::BAD_REQUEST),
+            (b"G(T / HTTP/1.1\r\n\r\n", StatusCode::BAD_REQUEST),
+            (b"BREW / HTTP/1.1\r\n\r\n", StatusCode::NOT_IMPLEMENTED),
+            (b"GET / HTTP/2.0\r\n\r\n", StatusCode::HTTP_VERSION_NOT_SUPPORTED),
+        ];
+
+        for (line, code) in cases.iter() {
+            let mut router = Router::new();
+            router.get("/", |_, _| Response::new(StatusCode::OK, b"OK"));
+
+            let mut stream = start(router, ServerConfig::default());
+            stream.write_all(line).unwrap();
+
+            let mut reader = BufReader::new(stream);
+            let response = Response::read_from(&mut reader).unwrap();
+            assert_eq!(response.code, *code, "{}", String::from_utf8_lossy(line));
+        }
+    }
+
+    #[test]
+    fn busy_server_refuses_client() {
+        let mut router = Router::new();
+        router.get("/slow", |_, _| {
+            thread::sleep(Duration::from_millis(500));
+            Response::new(StatusCode::OK, b"OK")
+        });
+
+        // Without a queue, a client is only taken by a worker waiting for one
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(router, ServerConfig { workers: 1, queue: 0, ..ServerConfig::default() });
+        thread::spawn(move || server.serve(listener));
+        thread::sleep(Duration::from_millis(100));
+
+        // The only worker is then busy with the first client
+        let mut first = TcpStream::connect(address).unwrap();
+        first.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // The body the server doesn't want must not reset the connection
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 65536\r\n\r\n").unwrap();
+        stream.write_all(&[b'a'; 65536]).unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut response = Response::read_from(&mut reader).unwrap();
+        assert_eq!(response.code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers.get("Connection").as_deref(), Some("close"));
+        assert_eq!(response.headers.get("Retry-After").as_deref(), Some("1"));
+        assert_eq!(response.body_string().unwrap(), "Server busy, try again later\n");
+    }
+
+    #[test]
+    fn ambiguous_length_closes_connection() {
+        let mut router = Router::new();
+        router.post("/", |request, _| {
+            let body = request.body_string().unwrap();
+            Response::new(StatusCode::OK, body.as_bytes())
+        });
+
+        let mut stream = start(router, ServerConfig::default());
+        stream.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap();
+        stream.write_all(b"5\r\nhello\r\n0\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut response = Response::read_from(&mut reader).unwrap();
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.headers.get("Connection").as_deref(), Some("close"));
+        assert_eq!(response.body_string().unwrap(), "hello");
+    }
+
+    #[test]
+    fn streamed_response_outlasts_request_timeout() {
+        let mut router = Router::new();
+        router.get("/events", |_, _| {
+            let events = (0..5).map(|i| {
+                thread::sleep(Duration::from_millis(100));
+                format!("data: {}\n\n", i)
+            });
+            Response::chunks(StatusCode::OK, events)
+        });
+
+        let mut stream = start(router, timeout(Duration::from_millis(250)));
+        stream.write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut response = Response::read_from(&mut reader).unwrap();
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "data: 0\n\ndata: 1\n\ndata: 2\n\ndata: 3\n\ndata: 4\n\n");
+    }
+
+    #[test]
+    fn streamed_request_outlasts_request_timeout() {
+        let mut router = Router::new();
+        router.post("/upload", |request, _| {
+            let body = request.body_string().unwrap();
+            Response::new(StatusCode::OK, body.as_bytes())
+        });
+
+        let config = ServerConfig { min_body_rate: 8, ..timeout(Duration::from_millis(250)) };
+        let mut stream = start(router, config);
+        stream.write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap();
+        for chunk in &["5\r\nfirst\r\n", "6\r\nsecond\r\n", "5\r\nthird\r\n0\r\n\r\n"] {
+            thread::sleep(Duration::from_millis(150));
+            stream.write_all(chunk.as_bytes()).unwrap();
+        }
+
+        let mut reader = BufReader::new(stream);
+        let mut response = Response::read_from(&mut reader).unwrap();
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "firstsecondthird");
+    }
+
+    #[test]
+    fn drip_fed_request_body_times_out() {
+        let mut router = Router::new();
+        router.post("/", |request, _| {
+            let body = request.body_string().unwrap_or_default();
+            Response::new(StatusCode::OK, body.as_bytes())
+        });
+
+        let config = ServerConfig { min_body_rate: 16, ..timeout(Duration::from_millis(250)) };
+        let mut stream = start(router, config);
+        stream.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\n").unwrap();
+
+        // One byte every 100ms never reaches 16 bytes in 250ms, even though
+        // each single read is quick enough
+        let mut writer = stream.try_clone().unwrap();
+        thread::spawn(move || {
+            for _ in 0..100 {
+                thread::sleep(Duration::from_millis(100));
+                if writer.write_all(b"x").is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut reader = BufReader::new(stream);
+        let response = Response::read_from(&mut reader).unwrap();
+        assert_eq!(response.code, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(response.headers.get("Connection").as_deref(), Some("close"));
+    }
+
+    #[test]
+    fn unread_request_body_is_skipped() {
+        let mut router = Router::new();
+        router.post("/ignore", |_, _| Response::new(StatusCode::OK, b"ignored"));
+        router.post("/partial", |request, _| {
+            let mut start = [0; 4];
+            if let Body::Stream(body) = &mut request.body {
+                body.read_exact(&mut start).unwrap();
+            }
+            Response::new(StatusCode::OK, &start)
+        });
+        router.get("/health", |_, _| Response::new(StatusCode::OK, b"OK"));
+
+        let mut stream = start(router, ServerConfig::default());
+        stream.write_all(b"POST /ignore HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello world").unwrap();
+        stream.write_all(b"POST /partial HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap();
+        stream.write_all(b"3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n").unwrap();
+        stream.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        // The connection stays open since each body was read to its end once
+        // the handler returned
+        let mut reader = BufReader::new(stream);
+        for expected in &["ignored", "abcd", "OK"] {
+            let mut response = Response::read_from(&mut reader).unwrap();
+            assert_eq!(response.code, StatusCode::OK);
+            assert_eq!(response.headers.get("Connection"), None);
+            assert_eq!(&response.body_string().unwrap(), expected);
+        }
+    }
+}
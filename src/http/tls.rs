@@ -0,0 +1,141 @@
+extern crate native_tls;
+
+use native_tls::{Certificate, Identity, Protocol, TlsConnector};
+
+use crate::http::Error;
+
+/// TLS settings for the HTTP client. The defaults trust the system's root
+/// certificates and check the server's name like a browser would
+#[derive(Default)]
+pub struct TlsConfig {
+    /// Certificates trusted in addition to the system's, e.g. a private CA
+    pub root_certificates: Vec<Certificate>,
+    /// Certificate and private key presented to servers asking for one
+    pub identity: Option<Identity>,
+    /// Oldest protocol version accepted. `None` keeps the library's default
+    pub min_protocol_version: Option<Protocol>,
+    /// Name sent with SNI and checked against the server's certificate
+    /// instead of the URL's host, e.g. to reach a stand-in for a real server
+    #[cfg(test)]
+    pub server_name: Option<String>,
+    /// Accept any certificate, even expired or self-signed ones. Only
+    /// available to tests since it makes TLS useless against an attacker
+    #[cfg(test)]
+    pub danger_accept_invalid_certs: bool,
+}
+
+impl TlsConfig {
+
+    /// Build the connector used for every HTTPS connection
+    pub fn connector(self) -> Result<TlsConnector, Error> {
+        let mut builder = TlsConnector::builder();
+
+        for certificate in self.root_certificates {
+            builder.add_root_certificate(certificate);
+        }
+
+        if let Some(identity) = self.identity {
+            builder.identity(identity);
+        }
+
+        if let Some(version) = self.min_protocol_version {
+            builder.min_protocol_version(Some(version));
+        }
+
+        #[cfg(test)]
+        builder.danger_accept_invalid_certs(self.danger_accept_invalid_certs);
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Load an identity from a PKCS#12 archive, e.g. a '.p12' or '.pfx' file
+/// holding a certificate chain and its private key
+pub fn load_identity(path: &str, password: &str) -> Result<Identity, Error> {
+    let archive = std::fs::read(path)?;
+    Ok(Identity::from_pkcs12(&archive, password)?)
+}
+
+/// Load a certificate from a PEM or DER file
+#[cfg(test)]
+pub fn load_certificate(path: &str) -> Result<Certificate, Error> {
+    let data = std::fs::read(path)?;
+
+    if data.starts_with(b"-----BEGIN") {
+        Ok(Certificate::from_pem(&data)?)
+    } else {
+        Ok(Certificate::from_der(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::http::{ProxyConfig, Request, Response, Router, StatusCode, Url, Verb};
+    use crate::http::client::{Client, ClientConfig};
+    use crate::http::server::{Server, ServerConfig};
+    use super::*;
+
+    /// Path of a file in the TLS fixtures. They hold a self-signed certificate
+    /// for 'localhost' valid for a century, its key, and a PKCS#12 archive of
+    /// both with the password 'secret', made with:
+    ///
+    /// openssl req -x509 -newkey rsa:2048 -nodes -days 36500 -subj /CN=localhost
+    ///     -addext subjectAltName=DNS:localhost -keyout key.pem -out certificate.pem
+    /// openssl pkcs12 -export -passout pass:secret -inkey key.pem -in certificate.pem -out identity.p12
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// The self-signed certificate, and the identity made of it and its key
+    fn self_signed() -> (Certificate, Identity) {
+        let certificate = load_certificate(&fixture("certificate.pem")).unwrap();
+        let identity = load_identity(&fixture("identity.p12"), "secret").unwrap();
+        (certificate, identity)
+    }
+
+    /// Serve HTTPS on a free port, returning the URL of the server
+    fn serve(identity: Identity) -> Url {
+        let mut router = Router::new();
+        router.get("/", |request, _| {
+            let body = format!("hello {}", request.url.get_arg("name").unwrap_or_else(|| "?".to_string()));
+            Response::new(StatusCode::OK, body.as_bytes())
+        });
+
+        let server = Server::new(router, ServerConfig::default()).with_tls(identity).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || server.serve(listener));
+
+        Url::parse(&format!("https://localhost:{}/?name=tls", port)).unwrap()
+    }
+
+    fn client(tls: TlsConfig) -> Client {
+        let config = ClientConfig { proxy: ProxyConfig::default(), ..ClientConfig::default() };
+        Client::with_tls(config, tls).unwrap()
+    }
+
+    #[test]
+    fn https_with_trusted_certificate() {
+        let (certificate, identity) = self_signed();
+        let url = serve(identity);
+
+        let tls = TlsConfig { root_certificates: vec![certificate], ..TlsConfig::default() };
+        let mut response = client(tls).send(Request::new(Verb::Get, url)).unwrap();
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "hello tls");
+    }
+
+    #[test]
+    fn https_with_untrusted_certificate() {
+        let (_, identity) = self_signed();
+        let url = serve(identity);
+
+        assert!(client(TlsConfig::default()).send(Request::new(Verb::Get, url.clone())).is_err());
+
+        let tls = TlsConfig { danger_accept_invalid_certs: true, ..TlsConfig::default() };
+        let mut response = client(tls).send(Request::new(Verb::Get, url)).unwrap();
+        assert_eq!(response.body_string().unwrap(), "hello tls");
+    }
+}
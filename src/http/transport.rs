@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Request, Response, RetryPolicy};
+use crate::http::client::Client;
+
+/// Something that sends HTTP requests and returns their responses, e.g. the
+/// socket based `Client` or the in-memory `MockTransport` used by tests
+pub trait Transport: Send + Sync {
+
+    /// Send an HTTP request and read the HTTP response
+    fn send(&self, request: Request) -> Result<Response, Error>;
+
+    /// Send an HTTP request, retrying transient failures according to the
+    /// policy. Transports that can't fail transiently send it once
+    fn send_with_retry(&self, request: Request, _policy: &RetryPolicy) -> Result<Response, Error> {
+        self.send(request)
+    }
+}
+
+impl Transport for Client {
+
+    fn send(&self, request: Request) -> Result<Response, Error> {
+        Client::send(self, request)
+    }
+
+    fn send_with_retry(&self, request: Request, policy: &RetryPolicy) -> Result<Response, Error> {
+        Client::send_with_retry(self, request, policy)
+    }
+}
+
+/// A shared transport, so that a test can keep a handle on a mock it gave away
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+
+    fn send(&self, request: Request) -> Result<Response, Error> {
+        (**self).send(request)
+    }
+
+    fn send_with_retry(&self, request: Request, policy: &RetryPolicy) -> Result<Response, Error> {
+        (**self).send_with_retry(request, policy)
+    }
+}
@@ -3,7 +3,7 @@ use crate::http::Error;
 use std::fmt;
 
 /// Representation for a HTTP verb, i.e. GET, POST, etc.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Verb {
     Connect,
     Delete,
@@ -34,6 +34,24 @@ impl Verb {
         }
     }
 
+    /// Check if sending the same request multiple times has the same effect
+    /// as sending it once
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            Verb::Delete | Verb::Get | Verb::Head | Verb::Options | Verb::Put | Verb::Trace => true,
+            Verb::Connect | Verb::Patch | Verb::Post => false,
+        }
+    }
+
+    /// Check if requests with this verb carry a body, which must be framed
+    /// even when it's empty
+    pub fn has_body(&self) -> bool {
+        match self {
+            Verb::Patch | Verb::Post | Verb::Put => true,
+            Verb::Connect | Verb::Delete | Verb::Get | Verb::Head | Verb::Options | Verb::Trace => false,
+        }
+    }
+
     /// Convert a HTTP verb to its string representation
     pub fn format(&self) -> String {
         match self {
@@ -0,0 +1,47 @@
+use crate::http::Error;
+
+use std::fmt;
+
+/// Version of the HTTP protocol spoken by a peer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+
+    /// Convert a string, e.g. 'HTTP/1.1', to a HTTP version
+    pub fn parse(text: &str) -> Result<Version, Error> {
+        match text {
+            "HTTP/1.0" => Ok(Version::Http10),
+            "HTTP/1.1" => Ok(Version::Http11),
+            _ => Err(Error::Version)
+        }
+    }
+
+    /// Check if connections stay open after a response unless a peer asks to
+    /// close them. HTTP/1.0 peers must ask with 'Connection: keep-alive'
+    pub fn keep_alive(&self) -> bool {
+        *self == Version::Http11
+    }
+
+    /// Check if bodies can be sent with the chunked method
+    pub fn chunked(&self) -> bool {
+        *self == Version::Http11
+    }
+
+    /// Convert a HTTP version to its string representation
+    pub fn format(&self) -> String {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }.to_string()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format())
+    }
+}
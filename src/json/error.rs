@@ -13,7 +13,7 @@ impl Error {
     /// A JSON token that has a syntax error
     pub fn invalid(pos: usize, kind: &str, value: &str) -> Error {
         Error {
-            pos: pos,
+            pos,
             message: format!("Invalid type {}: '{}'", kind, value)
         }
     }
@@ -16,6 +16,8 @@ pub struct Lexer {
     state: State,
     buffer: String,
     tokens: Vec<Token>,
+    /// The last character in a string was a backslash
+    escaped: bool,
 }
 
 impl Lexer {
@@ -25,6 +27,7 @@ impl Lexer {
             state: State::Neutral,
             buffer: String::new(),
             tokens: Vec::new(),
+            escaped: false,
         }
     }
 
@@ -39,13 +42,10 @@ impl Lexer {
             };
         }
 
-        let pos = text.len();
+        let pos = text.chars().count();
         match self.state {
             State::Neutral => Ok(self.tokens.clone()),
-            State::Text => {
-                self.add_text(pos)?;
-                Ok(self.tokens.clone())
-            },
+            State::Text => Err(Error::invalid(pos, "string", &self.buffer)),
             State::Keyword => {
                 self.add_keyword(pos)?;
                 Ok(self.tokens.clone())
@@ -78,6 +78,7 @@ impl Lexer {
             }
             '"' => {
                 self.state = State::Text;
+                self.escaped = false;
                 self.buffer.clear();
                 self.buffer.push(character);
             },
@@ -135,7 +136,7 @@ impl Lexer {
                 self.buffer.push(character);
                 Ok(())
             },
-            '-' | '.' | 'e' => {
+            '-' | '+' | '.' | 'e' | 'E' => {
                 self.buffer.push(character);
                 Ok(())
             },
@@ -148,31 +149,64 @@ impl Lexer {
         }
     }
 
-    /// Read the number in the buffer and convert to a token
+    /// Read the number in the buffer and convert to a token. Integers too
+    /// large for an i64 are kept as floats
     fn add_number(&mut self, index: usize) -> Result<(), Error> {
-        let number: f64 = self.buffer.parse()
-            .map_err(|_| Error::invalid(index, "digit", &self.buffer))
-            ?;
-
-        if self.buffer.contains('.') {
-            self.tokens.push(Token{
-                kind: TokenKind::Float(number), 
-                pos: index - self.buffer.len()
-            });
-        } else {
-            self.tokens.push(Token{
-                kind: TokenKind::Int(number as i64), 
-                pos: index - self.buffer.len()
-            });
+        if !Lexer::is_number(&self.buffer) {
+            return Err(Error::invalid(index, "digit", &self.buffer));
         }
 
+        let kind = match self.buffer.parse::<i64>() {
+            Ok(i) if !self.buffer.contains(['.', 'e', 'E']) => TokenKind::Int(i),
+            _ => TokenKind::Float(self.buffer.parse()
+                .map_err(|_| Error::invalid(index, "digit", &self.buffer))?)
+        };
+
+        self.tokens.push(Token{
+            kind,
+            pos: index - self.buffer.len()
+        });
+
         Ok(())
     }
 
+    /// Check a number against the JSON grammar of RFC 8259 section 6:
+    /// -? (0 | [1-9][0-9]*) (.[0-9]+)? ([eE][+-]?[0-9]+)?
+    fn is_number(text: &str) -> bool {
+        let mut rest = text.strip_prefix('-').unwrap_or(text);
+
+        let integer = rest;
+        match skip_digits(&mut rest) {
+            0 => return false,
+            n if n > 1 && integer.starts_with('0') => return false,
+            _ => {}
+        }
+
+        if let Some(fraction) = rest.strip_prefix('.') {
+            rest = fraction;
+            if skip_digits(&mut rest) == 0 {
+                return false;
+            }
+        }
+
+        if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+            rest = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            if skip_digits(&mut rest) == 0 {
+                return false;
+            }
+        }
+
+        rest.is_empty()
+    }
+
     /// Handle text inside a string
     fn lex_text(&mut self, index: usize, character: char) -> Result<(), Error> {
         self.buffer.push(character);
-        if character == '"' && !self.buffer.ends_with("\\\"") {
+        if self.escaped {
+            self.escaped = false;
+        } else if character == '\\' {
+            self.escaped = true;
+        } else if character == '"' {
             self.add_text(index)?;
             self.state = State::Neutral;
         }
@@ -181,13 +215,9 @@ impl Lexer {
 
     /// Convert text in the buffer to a string
     fn add_text(&mut self, index: usize) -> Result<(), Error> {
-        if !self.buffer.ends_with("\"") {
-            return Err(Error::invalid(index, "string", &self.buffer));
-        }
-
         self.tokens.push(Token{
             kind: TokenKind::Text(self.convert_string(index)?),
-            pos: index - self.buffer.len()
+            pos: index + 1 - self.buffer.chars().count()
         });
         Ok(())
     }
@@ -204,6 +234,8 @@ impl Lexer {
                         Some('n') => converted.push('\n'),
                         Some('t') => converted.push('\t'),
                         Some('r') => converted.push('\r'),
+                        Some('b') => converted.push('\u{8}'),
+                        Some('f') => converted.push('\u{c}'),
                         Some('u') => converted.push(Lexer::convert_unicode(index, &mut iter)?),
                         Some(c) => converted.push(*c),
                         None => return Ok(converted)
@@ -214,14 +246,107 @@ impl Lexer {
         }
     }
 
-    /// Convert unicode code points such as \u0123
+    /// Convert unicode code points such as \u00e9. Characters outside of the
+    /// Basic Multilingual Plane are written as a UTF-16 surrogate pair,
+    /// e.g. \ud83d\ude8c
     fn convert_unicode(index: usize, iter: &mut Chars) -> Result<char, Error> {
-        let digit: String = iter.take(3).collect();
+        let high = Lexer::convert_hex(index, iter)?;
 
-        let code: u32 = digit.parse()
-            .map_err(|_| Error::invalid(index, "string", &digit))?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            let escape: String = iter.by_ref().take(2).collect();
+            let low = Lexer::convert_hex(index, iter)?;
+            if escape != "\\u" || !(0xDC00..0xE000).contains(&low) {
+                return Err(Error::invalid(index, "string", "unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
 
         char::from_u32(code)
-            .ok_or(Error::invalid(index, "string", &digit))
+            .ok_or(Error::invalid(index, "string", &format!("\\u{:04x}", code)))
+    }
+
+    /// Convert the 4 hexadecimal digits of a unicode escape
+    fn convert_hex(index: usize, iter: &mut Chars) -> Result<u32, Error> {
+        let digits: String = iter.by_ref().take(4).collect();
+
+        if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::invalid(index, "string", &digits));
+        }
+
+        u32::from_str_radix(&digits, 16)
+            .map_err(|_| Error::invalid(index, "string", &digits))
+    }
+}
+
+/// Skip the digits at the start of a text and return how many there were
+fn skip_digits(text: &mut &str) -> usize {
+    let count = text.bytes().take_while(u8::is_ascii_digit).count();
+    *text = &text[count..];
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lex a text that holds a single token
+    fn lex_one(text: &str) -> TokenKind {
+        let mut tokens = Lexer::new().lex(text).unwrap();
+        assert_eq!(tokens.len(), 1, "{}", text);
+        tokens.remove(0).kind
+    }
+
+    fn text(json: &str) -> String {
+        match lex_one(json) {
+            TokenKind::Text(t) => t,
+            kind => panic!("{} isn't a string: {}", json, kind)
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(text(r#""a\"b\\c\/d""#), "a\"b\\c/d");
+        assert_eq!(text(r#""\r\n\t\b\f""#), "\r\n\t\u{8}\u{c}");
+        assert_eq!(text(r#""Universit\u00e9""#), "Université");
+        assert_eq!(text(r#""\u00C9t\u00e9""#), "Été");
+        assert_eq!(text(r#""bus \ud83d\ude8c""#), "bus \u{1F68C}");
+        assert_eq!(text(r#""""#), "");
+    }
+
+    #[test]
+    fn invalid_unicode_escapes() {
+        for json in &[r#""\ud83d""#, r#""\ud83dx\ude8c""#, r#""\ude8c""#, r#""\ud83d\u0041""#, r#""\u00g9""#, r#""\u00""#] {
+            assert!(Lexer::new().lex(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(matches!(lex_one("0"), TokenKind::Int(0)));
+        assert!(matches!(lex_one("-0"), TokenKind::Int(0)));
+        assert!(matches!(lex_one("-42"), TokenKind::Int(-42)));
+        assert!(matches!(lex_one("1e3"), TokenKind::Float(f) if f == 1000.0));
+        assert!(matches!(lex_one("2.5E-1"), TokenKind::Float(f) if f == 0.25));
+        assert!(matches!(lex_one("-0.5"), TokenKind::Float(f) if f == -0.5));
+        assert!(matches!(lex_one("10.0e+2"), TokenKind::Float(f) if f == 1000.0));
+        assert!(matches!(lex_one("99999999999999999999"), TokenKind::Float(f) if f == 1e20));
+        assert!(matches!(lex_one("-9223372036854775808"), TokenKind::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for json in &["1.2.3", "--1", "1e", "01", "-01", "1.", "-.5", "1.e5", "1e+", "1-2", "-"] {
+            assert!(Lexer::new().lex(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn structure_tokens() {
+        let tokens = Lexer::new().lex(r#"{"a": [1, true, null]}"#).unwrap();
+        let kinds: Vec<String> = tokens.iter().map(|t| t.kind.to_string()).collect();
+        assert_eq!(kinds, vec!["{", "\"a\"", ":", "[", "1", ",", "true", ",", "null", "]", "}"]);
+        assert_eq!(tokens[3].pos, 6);
     }
 }
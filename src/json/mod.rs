@@ -2,6 +2,7 @@ mod lexer;
 mod parser;
 mod error;
 mod token;
+mod writer;
 
 pub use parser::parse;
 pub use parser::JsonType;
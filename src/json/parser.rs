@@ -43,6 +43,12 @@ fn parse_tokens(tokens: &mut Iter<Token>) -> Result<JsonType, Error> {
 /// Read and convert tokens forming an array
 fn parse_array(tokens: &mut Iter<Token>) -> Result<JsonType, Error> {
     let mut items: Vec<JsonType> = Vec::new();
+
+    if let Some(Token{kind: TokenKind::ArrayClose, ..}) = tokens.clone().next() {
+        tokens.next();
+        return Ok(JsonType::Array(items));
+    }
+
     loop {
         items.push(parse_tokens(tokens)?);
 
@@ -59,6 +65,11 @@ fn parse_array(tokens: &mut Iter<Token>) -> Result<JsonType, Error> {
 fn parse_object(tokens: &mut Iter<Token>) -> Result<JsonType, Error> {
     let mut items: HashMap<String, JsonType> = HashMap::new();
 
+    if let Some(Token{kind: TokenKind::ObjClose, ..}) = tokens.clone().next() {
+        tokens.next();
+        return Ok(JsonType::Object(items));
+    }
+
     loop {
         //the key as in {"key": "value"}
         let key = match tokens.next() {
@@ -101,3 +112,44 @@ fn parse_object(tokens: &mut Iter<Token>) -> Result<JsonType, Error> {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_array_and_object() {
+        assert!(matches!(parse("[]"), Ok(JsonType::Array(a)) if a.is_empty()));
+        assert!(matches!(parse("{}"), Ok(JsonType::Object(o)) if o.is_empty()));
+        assert!(matches!(parse(" [ ] "), Ok(JsonType::Array(a)) if a.is_empty()));
+
+        let object = match parse(r#"{"horaires": [], "arret": {}}"#) {
+            Ok(JsonType::Object(o)) => o,
+            _ => panic!("expecting an object")
+        };
+        assert!(matches!(object.get("horaires"), Some(JsonType::Array(a)) if a.is_empty()));
+        assert!(matches!(object.get("arret"), Some(JsonType::Object(o)) if o.is_empty()));
+    }
+
+    #[test]
+    fn nested_values() {
+        let object = match parse(r#"{"horaires": [{"departMinutes": 4, "ntr": true}, null]}"#) {
+            Ok(JsonType::Object(o)) => o,
+            _ => panic!("expecting an object")
+        };
+        let items = match object.get("horaires") {
+            Some(JsonType::Array(a)) => a,
+            _ => panic!("expecting horaires")
+        };
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], JsonType::Object(o) if matches!(o.get("departMinutes"), Some(JsonType::Int(4)))));
+        assert!(matches!(items[1], JsonType::Null));
+    }
+
+    #[test]
+    fn invalid_structures() {
+        for json in &["[", "[1,]", r#"{"a"}"#, r#"{"a": 1,}"#, "[1 2]", ""] {
+            assert!(parse(json).is_err(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::fmt::Write;
+
+use crate::json::JsonType;
+
+/// Convert a JSON structure back to compact JSON text. Object keys are
+/// sorted so that the same structure is always written the same way
+impl fmt::Display for JsonType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonType::Null => write!(f, "null"),
+            JsonType::Bool(b) => write!(f, "{}", b),
+            JsonType::Int(i) => write!(f, "{}", i),
+            // JSON has no representation for infinity and NaN
+            JsonType::Float(n) if !n.is_finite() => write!(f, "null"),
+            // Keep the decimal point so that the number is read back as a float
+            JsonType::Float(n) if n.fract() == 0.0 => write!(f, "{:.1}", n),
+            JsonType::Float(n) => write!(f, "{}", n),
+            JsonType::String(s) => write_string(f, s),
+            JsonType::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            },
+            JsonType::Object(items) => {
+                let mut keys: Vec<&String> = items.keys().collect();
+                keys.sort();
+
+                f.write_char('{')?;
+                for (i, key) in keys.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", items[*key])?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Write a string between quotes, escaping quotes, backslashes and control
+/// characters
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?
+        }
+    }
+    f.write_char('"')
+}
@@ -1,18 +1,90 @@
 mod http;
 mod json;
+mod webhook;
 
-use std::net::{TcpListener, TcpStream};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 use chrono::prelude::*;
 
-use http::{Request, Verb, Response, Error, URL, client};
+use http::{Params, Request, Response, Url, Router, Verb, RetryPolicy, StatusCode, Transport};
+use http::cassette::Cassette;
+use http::client::{Client, ClientConfig};
+use http::server::{Server, ServerConfig};
 use json::parse;
 use json::JsonType;
+use webhook::InboundSms;
 
 struct RtcSms {
     username: String,
     password: String,
-    did: String 
+    did: String,
+    client: Box<dyn Transport>,
+    /// Retries of the calls to RTC
+    retry: RetryPolicy
+}
+
+/// Reasons why a SMS couldn't be answered
+#[derive(Debug)]
+enum SmsError {
+    /// The SMS or the request forwarding it is invalid
+    Input(String),
+    /// The RTC or voip.ms API failed or returned something unexpected
+    Upstream(String),
+    /// The RTC or voip.ms API took too long to respond
+    Timeout,
+}
+
+impl SmsError {
+
+    fn input(message: &str) -> SmsError {
+        SmsError::Input(message.to_string())
+    }
+
+    fn upstream(message: &str) -> SmsError {
+        SmsError::Upstream(message.to_string())
+    }
+
+    /// Status code of the response sent back for the error
+    fn status(&self) -> StatusCode {
+        match self {
+            SmsError::Input(_) => StatusCode::BAD_REQUEST,
+            SmsError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            SmsError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Display for SmsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmsError::Input(e) => write!(f, "{}", e),
+            SmsError::Upstream(e) => write!(f, "{}", e),
+            SmsError::Timeout => write!(f, "upstream API timed out"),
+        }
+    }
+}
+
+impl From<http::Error> for SmsError {
+    fn from(e: http::Error) -> Self {
+        match e {
+            http::Error::Timeout => SmsError::Timeout,
+            http::Error::Connect(ref e) if e.kind() == std::io::ErrorKind::TimedOut => SmsError::Timeout,
+            e => SmsError::Upstream(format!("{}", e))
+        }
+    }
+}
+
+impl From<webhook::Error> for SmsError {
+    fn from(e: webhook::Error) -> Self {
+        SmsError::Input(format!("{}", e))
+    }
+}
+
+impl From<json::Error> for SmsError {
+    fn from(e: json::Error) -> Self {
+        SmsError::Upstream(format!("{}", e))
+    }
 }
 
 fn main() {
@@ -21,92 +93,147 @@ fn main() {
     let password = std::env::args().nth(3).expect("no voip.ms password");
     let did = std::env::args().nth(4).expect("no voip.ms did");
 
-    let rtcsms = RtcSms {
-        username: username,
-        password: password,
-        did: did
-    };
+    let mut config = ServerConfig::default();
 
-    let listener = TcpListener::bind(host).expect("cannot bind to host");
+    if let Some(w) = std::env::args().nth(5) {
+        config.workers = w.parse().ok().filter(|w| *w > 0).expect("invalid number of workers");
+    }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(s) => {
-                if let Err(e) = rtcsms.handle_client(s) {
-                    println!("ERROR: {}", e);
-                }
-            },
-            Err(e) => {
-                println!("ERROR: {}", e);
-            }
-        }
+    if let Some(q) = std::env::args().nth(6) {
+        config.queue = q.parse().expect("invalid queue depth");
+    }
+
+    let rtcsms = Arc::new(RtcSms {
+        username,
+        password,
+        did,
+        client: transport(),
+        retry: RetryPolicy::default()
+    });
+
+    let mut server = Server::new(router(rtcsms), config);
+
+    // Serve HTTPS when given a PKCS#12 archive and its password
+    if let Some(path) = std::env::args().nth(7) {
+        let password = std::env::args().nth(8).unwrap_or_default();
+        let identity = http::load_identity(&path, &password).expect("cannot load TLS identity");
+        server = server.with_tls(identity).expect("cannot configure TLS");
     }
+
+    server.listen(&host).expect("cannot start server");
+}
+
+/// Route the requests to the handlers of the SMS service
+fn router(rtcsms: Arc<RtcSms>) -> Router {
+    // voip.ms calls back with a GET or a POST depending on how the DID is set
+    // up. A HEAD request must not send a SMS
+    let mut router = Router::new();
+    let webhook = rtcsms.clone();
+    router.get_only("/", move |request, _| webhook.handle_sms(request));
+    let lookup = rtcsms.clone();
+    router.get("/stops/{stop}/buses/{bus}", move |_, params| lookup.handle_next_bus(params));
+    router.post("/", move |request, _| rtcsms.handle_sms(request));
+    router.get("/health", |_, _| Response::new(StatusCode::OK, b"OK\n"));
+    router
+}
+
+/// Create the transport used to call the RTC and voip.ms APIs. Setting
+/// RTCSMS_RECORD to a file saves all their traffic to it, setting RTCSMS_REPLAY
+/// answers with the traffic saved instead of calling them
+fn transport() -> Box<dyn Transport> {
+    // The RTC website sets cookies that it expects back on later calls
+    let client = Box::new(Client::with_config(ClientConfig { cookies: true, ..ClientConfig::default() }));
+
+    let cassette = if let Ok(path) = std::env::var("RTCSMS_RECORD") {
+        Cassette::record(path, client)
+    } else if let Ok(path) = std::env::var("RTCSMS_REPLAY") {
+        Cassette::replay(path).expect("cannot load cassette")
+    } else {
+        return client;
+    };
+
+    // The password must not be saved, and the date changes every day
+    Box::new(cassette.ignore_arg("api_password").ignore_arg("date"))
 }
 
 /// Structure that manages reading HTTP requests, fetching information from RTC
 /// and sending an SMS with the next bus to pass
 impl RtcSms {
 
-    /// Handle new TCP socket from an HTTP client
-    pub fn handle_client(&self, mut stream: TcpStream) -> Result<(), Error> {
-        let request = Request::read(&mut stream)?;
-        println!("request: {} {} '{}'", 
-            request.verb, 
-            request.url.to_query(), 
-            request.body_string()
-        );
+    /// Handle a SMS forwarded by voip.ms and respond with what was sent back
+    pub fn handle_sms(&self, request: &mut Request) -> Response {
+        RtcSms::respond(self.handle_request(request))
+    }
 
-        let response = match self.handle_request(request) {
+    /// Respond with the minutes left before a bus passes at a stop, without
+    /// sending a SMS
+    pub fn handle_next_bus(&self, params: &Params) -> Response {
+        RtcSms::respond(self.next_bus_request(params))
+    }
+
+    /// Convert the result of a handler to a response
+    fn respond(result: Result<Response, SmsError>) -> Response {
+        match result {
             Ok(r) => r,
             Err(e) => {
                 let body = format!("{}\n", e);
-                Response::new(400, body.as_bytes())
+                Response::new(e.status(), body.as_bytes())
             }
-        };
+        }
+    }
 
-        println!("response: {} '{}'", 
-            response.code, 
-            response.body_string()
+    /// Find for what bus we want a schedule for and send an SMS back
+    fn handle_request(&self, request: &mut Request) -> Result<Response, SmsError> {
+        let sms = InboundSms::from_request(request)?;
+        println!("SMS {} from {} to {} on {}: '{}'",
+            sms.id.as_deref().unwrap_or("-"),
+            sms.from,
+            sms.to.as_deref().unwrap_or("-"),
+            sms.date.as_deref().unwrap_or("-"),
+            sms.message
         );
-        response.write(&mut stream)?;
 
-        Ok(())
+        let mut parts = sms.message.split_whitespace();
+
+        let stop = parts
+            .next()
+            .ok_or(SmsError::input("no stop number"))?
+            .parse::<i64>()
+            .map_err(|_| SmsError::input("invaid stop number"))?;
+
+        let bus = parts
+            .next()
+            .ok_or(SmsError::input("no bus number"))?
+            .parse::<i64>()
+            .map_err(|_| SmsError::input("invaid bus number"))?;
+
+        let minutes = self.get_next_bus(stop, bus)?;
+
+        self.send_sms(&sms.from, stop, bus, minutes)?;
+
+        let message = format!("Bus: {}, stop: {}, minutes: {}\n", bus, stop, minutes);
+        let response = Response::new(StatusCode::OK, message.as_bytes());
+        Ok(response)
     }
 
-    /// Find for what bus we want a schedule for and send an SMS back
-    fn handle_request(&self, request: Request) -> Result<Response, String> {
-        if let Verb::Get = request.verb {
-            let message = request.url.get_arg("message").ok_or("no message")?;
-            let dst = request.url.get_arg("dst").ok_or("no dst")?;
-
-            let mut parts = message.trim().splitn(2, "+");
-
-            let stop = parts
-                .next()
-                .ok_or(String::from("no stop number"))?
-                .parse::<i64>()
-                .map_err(|_| String::from("invaid stop number"))?;
-
-            let bus = parts
-                .next()
-                .ok_or(String::from("no bus number"))?
-                .parse::<i64>()
-                .map_err(|_| String::from("invaid bus number"))?;
-
-            let minutes = self.get_next_bus(stop, bus)?;
-
-            self.send_sms(dst, stop, bus, minutes)?;
-
-            let message = format!("Bus: {}, stop: {}, minutes: {}\n", bus, stop, minutes);
-            let response = Response::new(200, &message.as_bytes());
-            Ok(response)
-        } else {
-            Err(String::from("Unhandled HTTP verb"))
-        }
+    /// Find the next bus for the stop and bus in the path
+    fn next_bus_request(&self, params: &Params) -> Result<Response, SmsError> {
+        let stop = params.get("stop")
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or(SmsError::input("invalid stop number"))?;
+
+        let bus = params.get("bus")
+            .and_then(|b| b.parse::<i64>().ok())
+            .ok_or(SmsError::input("invalid bus number"))?;
+
+        let minutes = self.get_next_bus(stop, bus)?;
+
+        let message = format!("Bus: {}, stop: {}, minutes: {}\n", bus, stop, minutes);
+        Ok(Response::new(StatusCode::OK, message.as_bytes()))
     }
 
     /// Get the minutes left before next bus passes at a stop from the RTC API
-    fn get_next_bus(&self, stop: i64, bus: i64) -> Result<i64, String> {
+    fn get_next_bus(&self, stop: i64, bus: i64) -> Result<i64, SmsError> {
             let mut response = self.rtc_json(stop, bus, 2)?;
 
             if let Some(JsonType::Null) = response.get("horaires") {
@@ -119,45 +246,51 @@ impl RtcSms {
 
             let schedule = match response.get("horaires") {
                 Some(JsonType::Array(a)) => a,
-                _ => return Err(String::from("missing horaires"))
+                _ => return Err(SmsError::upstream("missing horaires"))
             };
 
-            let next = match schedule.get(0) {
+            let next = match schedule.first() {
                 Some(JsonType::Object(o)) => o,
-                _ => return Err(String::from("missing first horaire"))
+                _ => return Err(SmsError::upstream("missing first horaire"))
             };
 
+            // RTC lists the stop without a departure when the bus doesn't
+            // pass there, which is a mistake in the SMS
             match next.get("departMinutes") {
                 Some(JsonType::Int(m)) => Ok(*m),
-                _ => Err(String::from("missing departMinutes"))
+                _ => Err(SmsError::input("no departure for this bus at this stop"))
             }
     }
 
     /// Send a HTTP request to the RTC API and convert the response to JSON
-    fn rtc_json(&self, stop: i64, bus: i64, direction: i64) -> Result<HashMap<String, JsonType>, String> {
+    fn rtc_json(&self, stop: i64, bus: i64, direction: i64) -> Result<HashMap<String, JsonType>, SmsError> {
         let date = Local::now().format("%Y%m%d");
 
-        let mut url = URL::parse("https://wssiteweb.rtcquebec.ca/api/v2/horaire/BorneVirtuelle_ArretParcours/")?;
+        let mut url = Url::parse("https://wssiteweb.rtcquebec.ca/api/v2/horaire/BorneVirtuelle_ArretParcours/")?;
         url.add_arg("noParcours", &bus.to_string());
         url.add_arg("noArret", &stop.to_string());
         url.add_arg("codeDirection", &direction.to_string());
         url.add_arg("date", &date.to_string());
 
         let request = Request::new(Verb::Get, url);
-        let response = client::send(request)?;
-        let body = response.body_string();
+        let mut response = self.client.send_with_retry(request, &self.retry)?;
+        let body = response.body_string()?;
+
+        if !response.code.is_success() {
+            return Err(SmsError::Upstream(format!("RTC responded {}: {}", response.code, body)));
+        }
 
         match parse(&body)? {
             JsonType::Object(o) => Ok(o),
-            _ => Err(format!("Invalid RTC response: {}", body))
+            _ => Err(SmsError::Upstream(format!("Invalid RTC response: {}", body)))
         }
     }
 
     /// Send a SMS back to the user using the voip.ms API
-    fn send_sms(&self, dst: &str, stop: i64, bus: i64, minutes: i64) -> Result<(), String> {
-        let message = format!("{}+-+{}:+{}+minutes", stop, bus, minutes);
+    fn send_sms(&self, dst: &str, stop: i64, bus: i64, minutes: i64) -> Result<(), SmsError> {
+        let message = format!("{} - {}: {} minutes", stop, bus, minutes);
 
-        let mut url = URL::parse("https://voip.ms/api/v1/rest.php")?;
+        let mut url = Url::parse("https://voip.ms/api/v1/rest.php")?;
         url.add_arg("api_username", &self.username);
         url.add_arg("api_password", &self.password);
         url.add_arg("method", "sendSMS");
@@ -165,12 +298,224 @@ impl RtcSms {
         url.add_arg("dst", dst);
         url.add_arg("message", &message);
 
+        // A SMS that may have been sent must not be sent again, the user
+        // would get it twice
         let request = Request::new(Verb::Get, url);
-        let response = client::send(request)?;
+        let mut response = self.client.send_with_retry(request, &RetryPolicy::connect_only())?;
+        let body = response.body_string()?;
 
-        println!("voip.ms response: {} '{}'", response.code, response.body_string());
+        println!("voip.ms response: {} '{}'", response.code, body);
+
+        if !response.code.is_success() {
+            return Err(SmsError::Upstream(format!("voip.ms responded {}", response.code)));
+        }
 
         Ok(())
 
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::http::mock::{Matcher, MockTransport};
+    use super::*;
+
+    const RTC_HOST: &str = "wssiteweb.rtcquebec.ca";
+    const RTC_PATH: &str = "/api/v2/horaire/BorneVirtuelle_ArretParcours/";
+
+    fn rtcsms(mock: &Arc<MockTransport>) -> RtcSms {
+        RtcSms {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+            did: "4185550000".to_string(),
+            client: Box::new(mock.clone()),
+            retry: RetryPolicy::default()
+        }
+    }
+
+    fn rtc(direction: &str) -> Matcher {
+        Matcher::new(Verb::Get, RTC_HOST, RTC_PATH).arg("codeDirection", direction)
+    }
+
+    fn voipms() -> Matcher {
+        Matcher::new(Verb::Get, "voip.ms", "/api/v1/rest.php").arg("method", "sendSMS")
+    }
+
+    fn sms_get() -> Request {
+        let url = Url::parse("http://localhost/?from=4185551234&message=1234%20800").unwrap();
+        Request::new(Verb::Get, url)
+    }
+
+    fn sms_post(content_type: &str, body: &str) -> Request {
+        let mut request = Request::new(Verb::Post, Url::parse("http://localhost/").unwrap());
+        request.headers.add("Content-Type", content_type);
+        request.body = body.into();
+        request
+    }
+
+    /// Run a SMS through the flow, and return the response with the requests
+    /// sent to the APIs
+    fn handle(mock: &Arc<MockTransport>, mut request: Request) -> (Response, Vec<Request>) {
+        let response = rtcsms(mock).handle_sms(&mut request);
+        (response, mock.take_requests())
+    }
+
+    fn schedule(minutes: i64) -> String {
+        format!("{{\"horaires\":[{{\"departMinutes\":{}}},{{\"departMinutes\":{}}}]}}", minutes, minutes + 15)
+    }
+
+    fn check_sent(requests: &[Request], minutes: i64) {
+        let rtc = &requests[0];
+        assert_eq!(rtc.url.host, RTC_HOST);
+        assert_eq!(rtc.url.get_arg("noArret").as_deref(), Some("1234"));
+        assert_eq!(rtc.url.get_arg("noParcours").as_deref(), Some("800"));
+        assert_eq!(rtc.url.get_arg("codeDirection").as_deref(), Some("2"));
+
+        let sms = requests.last().unwrap();
+        assert_eq!(sms.url.host, "voip.ms");
+        assert_eq!(sms.url.get_arg("api_username").as_deref(), Some("user"));
+        assert_eq!(sms.url.get_arg("api_password").as_deref(), Some("secret"));
+        assert_eq!(sms.url.get_arg("method").as_deref(), Some("sendSMS"));
+        assert_eq!(sms.url.get_arg("did").as_deref(), Some("4185550000"));
+        assert_eq!(sms.url.get_arg("dst").as_deref(), Some("4185551234"));
+        assert_eq!(sms.url.get_arg("message").as_deref(), Some(format!("1234 - 800: {} minutes", minutes).as_str()));
+    }
+
+    #[test]
+    fn sms_from_get_query() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond(rtc("2"), StatusCode::OK, schedule(7).as_bytes());
+        mock.respond(voipms(), StatusCode::OK, b"{\"status\":\"success\"}");
+
+        let (mut response, requests) = handle(&mock, sms_get());
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "Bus: 800, stop: 1234, minutes: 7\n");
+        assert_eq!(requests.len(), 2);
+        check_sent(&requests, 7);
+    }
+
+    #[test]
+    fn sms_from_form_post() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond(rtc("2"), StatusCode::OK, schedule(3).as_bytes());
+        mock.respond(voipms(), StatusCode::OK, b"{\"status\":\"success\"}");
+
+        let request = sms_post("application/x-www-form-urlencoded", "id=42&from=4185551234&to=4185550000&message=1234+800");
+        let (response, requests) = handle(&mock, request);
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(requests.len(), 2);
+        check_sent(&requests, 3);
+    }
+
+    #[test]
+    fn sms_from_json_post() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond(rtc("2"), StatusCode::OK, schedule(12).as_bytes());
+        mock.respond(voipms(), StatusCode::OK, b"{\"status\":\"success\"}");
+
+        let request = sms_post("application/json", "{\"id\": 42, \"from\": \"4185551234\", \"message\": \"1234 800\"}");
+        let (response, requests) = handle(&mock, request);
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(requests.len(), 2);
+        check_sent(&requests, 12);
+    }
+
+    #[test]
+    fn sms_tries_other_direction() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond(rtc("2"), StatusCode::OK, b"{\"horaires\":null}");
+        mock.respond(rtc("3"), StatusCode::OK, schedule(9).as_bytes());
+        mock.respond(voipms(), StatusCode::OK, b"{\"status\":\"success\"}");
+
+        let (response, requests) = handle(&mock, sms_get());
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].url.host, RTC_HOST);
+        assert_eq!(requests[1].url.get_arg("codeDirection").as_deref(), Some("3"));
+        check_sent(&requests, 9);
+    }
+
+    #[test]
+    fn rtc_server_error_is_bad_gateway() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond(rtc("2"), StatusCode::SERVICE_UNAVAILABLE, b"down");
+
+        let (response, requests) = handle(&mock, sms_get());
+        assert_eq!(response.code, StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.len(), 1);
+    }
+
+    #[test]
+    fn rtc_timeout_is_gateway_timeout() {
+        let mock = Arc::new(MockTransport::new());
+        mock.fail_with(rtc("2"), || http::Error::Timeout);
+
+        let (response, requests) = handle(&mock, sms_get());
+        assert_eq!(response.code, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(requests.len(), 1);
+    }
+
+    #[test]
+    fn missing_depart_minutes_is_bad_request() {
+        let mock = Arc::new(MockTransport::new());
+        mock.respond(rtc("2"), StatusCode::OK, b"{\"horaires\":[{\"depart\":\"12:30\"}]}");
+
+        let (response, requests) = handle(&mock, sms_get());
+        assert_eq!(response.code, StatusCode::BAD_REQUEST);
+        assert_eq!(requests.len(), 1);
+    }
+
+    /// Replay the RTC and voip.ms traffic of a SMS asking for bus 800 at stop
+    /// 1234, where RTC only has a schedule for direction 3
+    fn replay() -> RtcSms {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cassettes/rtc.json");
+        let cassette = Cassette::replay(path).unwrap()
+            .ignore_arg("api_password")
+            .ignore_arg("date");
+
+        RtcSms {
+            username: "user@example.com".to_string(),
+            password: "secret".to_string(),
+            did: "4185550000".to_string(),
+            client: Box::new(cassette),
+            retry: RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn next_bus_from_cassette() {
+        assert_eq!(replay().get_next_bus(1234, 800).unwrap(), 4);
+    }
+
+    #[test]
+    fn sms_from_cassette() {
+        let mut response = replay().handle_sms(&mut sms_get());
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "Bus: 800, stop: 1234, minutes: 4\n");
+    }
+
+    #[test]
+    fn next_bus_from_path() {
+        let router = router(Arc::new(replay()));
+
+        let url = Url::parse("http://localhost/stops/1234/buses/800").unwrap();
+        let mut response = router.handle(&mut Request::new(Verb::Get, url));
+        assert_eq!(response.code, StatusCode::OK);
+        assert_eq!(response.body_string().unwrap(), "Bus: 800, stop: 1234, minutes: 4\n");
+
+        let url = Url::parse("http://localhost/stops/1234/buses/abc").unwrap();
+        let response = router.handle(&mut Request::new(Verb::Get, url));
+        assert_eq!(response.code, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn invalid_sms_is_bad_request() {
+        let mock = Arc::new(MockTransport::new());
+        let url = Url::parse("http://localhost/?from=4185551234&message=hello").unwrap();
+
+        let (response, requests) = handle(&mock, Request::new(Verb::Get, url));
+        assert_eq!(response.code, StatusCode::BAD_REQUEST);
+        assert!(requests.is_empty());
+    }
+}
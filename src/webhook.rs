@@ -0,0 +1,202 @@
+use std::fmt;
+
+use crate::http;
+use crate::http::{Request, Verb};
+use crate::json;
+use crate::json::JsonType;
+
+/// Reasons why a SMS forwarded by voip.ms can't be read
+#[derive(Debug)]
+pub enum Error {
+    /// A field the SMS needs is missing or empty
+    Missing(&'static str),
+    /// The body of the request can't be read or has an unsupported type
+    Body(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Missing(field) => write!(f, "no {}", field),
+            Error::Body(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(e: http::Error) -> Self {
+        Error::Body(format!("{}", e))
+    }
+}
+
+impl From<json::Error> for Error {
+    fn from(e: json::Error) -> Self {
+        Error::Body(format!("{}", e))
+    }
+}
+
+/// A SMS sent to the DID and forwarded by voip.ms
+#[derive(Debug)]
+pub struct InboundSms {
+    /// Number that sent the SMS, where the answer goes
+    pub from: String,
+    /// Number the SMS was sent to
+    pub to: Option<String>,
+    pub message: String,
+    /// Identifier given to the SMS by voip.ms
+    pub id: Option<String>,
+    /// When voip.ms received the SMS, as it formats it
+    pub date: Option<String>,
+}
+
+impl InboundSms {
+
+    /// Read a SMS delivered as the query arguments of a GET, or as the form,
+    /// multipart or JSON body of a POST. Fields of the body have priority over query
+    /// arguments. The sender can also be given as 'dst', like the callback URL
+    /// originally configured in voip.ms does
+    pub fn from_request(request: &mut Request) -> Result<InboundSms, Error> {
+        let mut fields = InboundSms::body_fields(request)?;
+        fields.extend(request.url.args());
+
+        let field = |name: &str| fields.iter()
+            .find(|(n, v)| n == name && !v.is_empty())
+            .map(|(_, v)| v.to_string());
+
+        Ok(InboundSms {
+            from: field("from").or(field("dst")).ok_or(Error::Missing("from"))?,
+            to: field("to"),
+            message: field("message").ok_or(Error::Missing("message"))?,
+            id: field("id"),
+            date: field("date"),
+        })
+    }
+
+    /// Read the fields of a POST's body, depending on its content type
+    fn body_fields(request: &mut Request) -> Result<Vec<(String, String)>, Error> {
+        if request.verb != Verb::Post {
+            return Ok(Vec::new());
+        }
+
+        let media = match request.headers.content_type() {
+            Some(m) => m,
+            None => return Ok(Vec::new())
+        };
+
+        if media.essence() == "application/x-www-form-urlencoded" {
+            Ok(request.form()?)
+        } else if media.essence() == "application/json" || media.subtype.ends_with("+json") {
+            json_fields(&request.body_string()?)
+        } else if media.essence() == "multipart/form-data" {
+            // Attachments, e.g. the pictures of a MMS, are left out
+            let fields = request.multipart()?.into_iter()
+                .filter(|p| !p.is_file() && p.content_type().kind == "text")
+                .map(|p| (p.name.clone(), p.body_string()))
+                .collect();
+            Ok(fields)
+        } else {
+            Err(Error::Body(format!("unsupported content type {}", media.essence())))
+        }
+    }
+}
+
+/// Convert the fields of a JSON object to text. Fields that are objects,
+/// arrays or null are skipped
+fn json_fields(body: &str) -> Result<Vec<(String, String)>, Error> {
+    let object = match json::parse(body)? {
+        JsonType::Object(o) => o,
+        _ => return Err(Error::Body("expecting a JSON object".to_string()))
+    };
+
+    let fields = object.into_iter()
+        .filter_map(|(name, value)| match value {
+            JsonType::String(s) => Some((name, s)),
+            JsonType::Int(i) => Some((name, i.to_string())),
+            JsonType::Float(f) => Some((name, f.to_string())),
+            JsonType::Bool(b) => Some((name, b.to_string())),
+            _ => None
+        })
+        .collect();
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::Url;
+    use super::*;
+
+    fn post(query: &str, content_type: &str, body: &str) -> Request {
+        let mut request = Request::new(Verb::Post, Url::parse(&format!("http://localhost/{}", query)).unwrap());
+        request.headers.add("Content-Type", content_type);
+        request.body = body.into();
+        request
+    }
+
+    #[test]
+    fn sms_from_query() {
+        let url = Url::parse("http://localhost/?to=4185550000&from=4185551234&message=1234%20800&id=42&date=2020-01-02+03%3A04%3A05").unwrap();
+        let sms = InboundSms::from_request(&mut Request::new(Verb::Get, url)).unwrap();
+        assert_eq!(sms.from, "4185551234");
+        assert_eq!(sms.to.as_deref(), Some("4185550000"));
+        assert_eq!(sms.message, "1234 800");
+        assert_eq!(sms.id.as_deref(), Some("42"));
+        assert_eq!(sms.date.as_deref(), Some("2020-01-02 03:04:05"));
+    }
+
+    #[test]
+    fn sender_can_be_dst() {
+        let url = Url::parse("http://localhost/?dst=4185551234&message=1234+800").unwrap();
+        let sms = InboundSms::from_request(&mut Request::new(Verb::Get, url)).unwrap();
+        assert_eq!(sms.from, "4185551234");
+        assert_eq!(sms.to, None);
+    }
+
+    #[test]
+    fn sms_from_form() {
+        let mut request = post("?message=ignored", "application/x-www-form-urlencoded; charset=utf-8", "from=4185551234&message=1234+800&id=42");
+        let sms = InboundSms::from_request(&mut request).unwrap();
+        assert_eq!(sms.from, "4185551234");
+        assert_eq!(sms.message, "1234 800");
+        assert_eq!(sms.id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn sms_from_json() {
+        let body = "{\"id\": 42, \"from\": \"4185551234\", \"to\": null, \"message\": \"1234 800\", \"media\": []}";
+        let mut request = post("?to=4185550000", "application/json", body);
+        let sms = InboundSms::from_request(&mut request).unwrap();
+        assert_eq!(sms.from, "4185551234");
+        assert_eq!(sms.to.as_deref(), Some("4185550000"));
+        assert_eq!(sms.message, "1234 800");
+        assert_eq!(sms.id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn sms_from_multipart() {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"from\"\r\n\r\n4185551234\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"message\"\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n1234 800\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"id\"; filename=\"photo.jpg\"\r\nContent-Type: image/jpeg\r\n\r\nJFIF\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"date\"\r\nContent-Type: application/octet-stream\r\n\r\n\x00\r\n\
+            --b--\r\n";
+        let mut request = post("", "multipart/form-data; boundary=b", body);
+        let sms = InboundSms::from_request(&mut request).unwrap();
+        assert_eq!(sms.from, "4185551234");
+        assert_eq!(sms.message, "1234 800");
+        assert_eq!(sms.id, None);
+        assert_eq!(sms.date, None);
+    }
+
+    #[test]
+    fn invalid_callbacks() {
+        let url = Url::parse("http://localhost/?from=4185551234&message=").unwrap();
+        let error = InboundSms::from_request(&mut Request::new(Verb::Get, url)).unwrap_err();
+        assert_eq!(error.to_string(), "no message");
+
+        let mut request = post("", "application/json", "[\"1234 800\"]");
+        assert!(matches!(InboundSms::from_request(&mut request), Err(Error::Body(_))));
+
+        let mut request = post("", "text/plain", "1234 800");
+        assert!(matches!(InboundSms::from_request(&mut request), Err(Error::Body(_))));
+    }
+}